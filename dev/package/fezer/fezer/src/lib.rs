@@ -0,0 +1,196 @@
+/*
+
+    fezer非同期ランタイム
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    fezer_executorのExecutorと、fezer_macrosの属性マクロをまとめて公開する。
+
+    - `#[fezer::main]` で `async fn main()` をExecutor上で実行する。戻り値に
+      `Result` を返すこともできる
+    - `#[fezer::test]` で `async fn` のテストをテストごとに新しいExecutorで実行
+      する。タイムアウトを指定でき、生成したタスクがパニックになるとテストは失
+      敗する
+
+    # 使用例
+
+    ```rust
+    #[fezer::main(worker_threads = 4)]
+    async fn main() -> std::io::Result<()>
+    {
+        let value = fezer::spawn(async { 1 + 1 }).await.unwrap();
+        println!("{}", value);
+        Ok(())
+    }
+
+    #[fezer::test(flavor = "current_thread", timeout_ms = 1000)]
+    async fn test_spawn()
+    {
+        assert_eq!(2, fezer::spawn(async { 1 + 1 }).await.unwrap());
+    }
+    ```
+
+*/
+
+//  マクロが展開する `::fezer` のパスをこのクレート内でも解決できるようにする
+extern crate self as fezer;
+
+#[doc(hidden)]
+pub mod test_runner;
+
+pub use fezer_executor::*;
+pub use fezer_macros::{ main, test };
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::Arc;
+
+    //--------------------------------------------------------------------------
+    //  マルチスレッドのmain関数
+    //--------------------------------------------------------------------------
+    #[crate::main(worker_threads = 2)]
+    async fn multi_thread_main() -> Result<usize, crate::error::JoinError>
+    {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..10).map(|_|
+        {
+            let counter = counter.clone();
+            crate::spawn(async move { counter.fetch_add(1, Ordering::AcqRel) })
+        })
+        .collect();
+        for handle in handles
+        {
+            handle.await?;
+        }
+        Ok(counter.load(Ordering::Acquire))
+    }
+
+    //--------------------------------------------------------------------------
+    //  シングルスレッドのmain関数
+    //--------------------------------------------------------------------------
+    #[crate::main(flavor = "current_thread")]
+    async fn current_thread_main() -> std::thread::ThreadId
+    {
+        crate::spawn(async { std::thread::current().id() }).await.unwrap()
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_main
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_main()
+    {
+        assert_eq!(Ok(10), multi_thread_main());
+        assert_eq!(std::thread::current().id(), current_thread_main());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_main_error
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_main_error()
+    {
+        #[crate::main(flavor = "current_thread")]
+        async fn failing_main() -> Result<(), crate::error::JoinError>
+        {
+            crate::spawn(async { panic!("task panicked") }).await?;
+            Ok(())
+        }
+
+        assert!(failing_main().unwrap_err().is_panic());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_async_test
+    //--------------------------------------------------------------------------
+    #[crate::test(worker_threads = 2)]
+    async fn test_async_test() -> Result<(), crate::error::JoinError>
+    {
+        let value = crate::spawn(async { 1 + 1 }).await?;
+        assert_eq!(2, value);
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_current_thread_test
+    //--------------------------------------------------------------------------
+    #[crate::test(flavor = "current_thread")]
+    async fn test_current_thread_test()
+    {
+        //  タスクはテストのスレッドで生成された順に実行される
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handles: Vec<_> = (0..5).map(|n|
+        {
+            let order = order.clone();
+            crate::spawn(async move
+            {
+                assert_eq!(Some("tests::test_current_thread_test"), std::thread::current().name());
+                order.lock().unwrap().push(n);
+            })
+        })
+        .collect();
+        for handle in handles
+        {
+            handle.await.unwrap();
+        }
+        assert_eq!(vec![0, 1, 2, 3, 4], *order.lock().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_task_panic_fails_test
+    //--------------------------------------------------------------------------
+    #[crate::test]
+    #[should_panic(expected = "a task spawned by the test panicked: task panicked")]
+    async fn test_task_panic_fails_test()
+    {
+        let _ = crate::spawn(async { panic!("task panicked") }).await;
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_detached_task_panic_fails_test
+    //--------------------------------------------------------------------------
+    #[crate::test]
+    #[should_panic(expected = "a task spawned by the test panicked: detached task panicked")]
+    async fn test_detached_task_panic_fails_test()
+    {
+        //  本体が待たずに完了したタスクのパニックも検出される
+        crate::spawn(async
+        {
+            crate::sleep(core::time::Duration::from_millis(20)).await;
+            panic!("detached task panicked");
+        });
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_background_task_is_cancelled
+    //--------------------------------------------------------------------------
+    #[crate::test(timeout_ms = 5000)]
+    async fn test_background_task_is_cancelled()
+    {
+        //  終了しないタスクが残っていてもテストは終了する
+        crate::spawn(async
+        {
+            loop
+            {
+                crate::sleep(core::time::Duration::from_millis(10)).await;
+            }
+        });
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_timeout
+    //--------------------------------------------------------------------------
+    #[crate::test(timeout_ms = 50)]
+    #[should_panic(expected = "test timed out after 50ms")]
+    async fn test_timeout()
+    {
+        core::future::pending::<()>().await;
+    }
+}
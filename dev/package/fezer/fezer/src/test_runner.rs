@@ -0,0 +1,121 @@
+/*
+
+    `#[fezer::test]` のテストを実行する
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `#[fezer::test]` が展開するコードから呼び出される。マクロの外部から直接使
+    用することは想定していない。
+
+    - テストごとに新しいExecutorを生成し、テスト名のスレッドで本体を
+      `Executor::block_on()` で実行する
+    - 本体が完了した後も、本体が生成したタスクの完了を `DRAIN_TIMEOUT` まで待
+      つ。いずれかのタスクがパニックになっていた場合は、本体が成功してもテスト
+      を失敗させる
+    - `DRAIN_TIMEOUT` までに完了しなかったタスクは、Executorと共に破棄して数を
+      標準エラー出力に報告する。終了しないバックグラウンドのタスクがあってもテ
+      ストは終了する
+    - タイムアウトを指定した場合、時間内に終わらなければテストを失敗させる。タ
+      イムアウトしたテストのスレッドは停止できないので、そのまま残される
+
+    本体のパニックはテストのスレッドで再開するので、`#[should_panic]` と組み合
+    わせることができる。
+
+*/
+
+use crate::{ Executor, Flavor };
+use crate::error::panic_message;
+
+use core::future::Future;
+use core::time::Duration;
+use std::panic::resume_unwind;
+use std::process::{ ExitCode, Termination };
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, RecvTimeoutError };
+
+//  本体の完了後に、残りのタスクの完了を待つ時間
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+//------------------------------------------------------------------------------
+//  テストを新しいExecutorで実行
+//  `name` は `module_path!()` から始まるテストの名前
+//
+//  ※ テストが失敗した場合はpanic
+//------------------------------------------------------------------------------
+pub fn run_test<F, Fut>(
+    name: &str,
+    flavor: Flavor,
+    num_threads: usize,
+    timeout: Option<Duration>,
+    f: F,
+)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future,
+    Fut::Output: Termination,
+{
+    //  テストハーネスと同じく、クレート名を除いた名前をスレッド名にする
+    let name = name.split_once("::").map_or(name, |(_, path)| path);
+
+    //  スレッドが終了すると送信側がドロップされ、受信側に通知される
+    let (sender, receiver) = channel::<()>();
+    let handle = std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move ||
+        {
+            let _sender = sender;
+            let panicked = Arc::new(Mutex::new(None));
+            let panicked_clone = panicked.clone();
+            let executor = Executor::builder("test")
+                .flavor(flavor)
+                .num_threads(num_threads)
+                .panic_handler(move |payload|
+                {
+                    let message = panic_message(payload).unwrap_or("Box<dyn Any>");
+                    panicked_clone.lock().unwrap().get_or_insert_with(|| message.to_string());
+                })
+                .build()
+                .expect("failed to build fezer runtime");
+
+            let code = executor.block_on(f()).report();
+
+            //  本体が待たなかったタスクのパニックも検出できるように、残りのタ
+            //  スクの完了を待つ。完了しなかったタスクはExecutorと共に破棄される
+            let num_remaining = executor.run_timeout(DRAIN_TIMEOUT);
+            let panicked = panicked.lock().unwrap().take();
+            if let Some(message) = panicked
+            {
+                panic!("a task spawned by the test panicked: {}", message);
+            }
+            if num_remaining > 0
+            {
+                eprintln!
+                (
+                    "{} task(s) spawned by the test were still running {:?} after the test \
+                     finished and were cancelled",
+                    num_remaining,
+                    DRAIN_TIMEOUT
+                );
+            }
+            assert!
+            (
+                code == ExitCode::SUCCESS,
+                "the test returned a termination value with a non-zero status code"
+            );
+        })
+        .expect("failed to spawn the test thread");
+
+    if let Some(timeout) = timeout
+    {
+        if let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(timeout)
+        {
+            panic!("test timed out after {:?}", timeout);
+        }
+    }
+    if let Err(payload) = handle.join()
+    {
+        resume_unwind(payload);
+    }
+}
@@ -0,0 +1,161 @@
+/*
+
+    現在のスレッドでFutureを完了まで実行する
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `block_on()` は渡されたFutureを呼び出し元のスレッドでポーリングし、完了する
+    まで待つ。Futureが `Pending` を返している間はスレッドをパークし、Wakerが呼
+    び出されるとアンパークして再びポーリングする。Wakerは任意のスレッドから呼
+    び出すことができる。
+
+    ```rust
+    let value = fezer_executor::block_on(async { compute().await });
+    ```
+
+    `block_on()` はExecutorを持たないので、Futureの内部から `spawn()` を呼び出
+    すことはできない。タスクを生成する場合は `Executor::block_on()` を用いる。
+
+*/
+
+use core::future::Future;
+use core::pin::pin;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use core::task::{ Context, Poll };
+use std::sync::Arc;
+use std::task::{ Wake, Waker };
+use std::thread::Thread;
+
+//------------------------------------------------------------------------------
+//  スレッドをアンパークするWaker
+//------------------------------------------------------------------------------
+struct ThreadWaker
+{
+    //  Futureをポーリングするスレッド
+    thread: Thread,
+
+    //  最後のポーリングの後で起床されたかどうか
+    woken: AtomicBool,
+}
+
+impl Wake for ThreadWaker
+{
+    //--------------------------------------------------------------------------
+    //  wake
+    //--------------------------------------------------------------------------
+    fn wake( self: Arc<Self> )
+    {
+        self.wake_by_ref();
+    }
+
+    //--------------------------------------------------------------------------
+    //  wake_by_ref
+    //--------------------------------------------------------------------------
+    fn wake_by_ref( self: &Arc<Self> )
+    {
+        self.woken.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Futureを完了まで実行し、待機の合間に `run_tasks` を呼び出す
+//  `run_tasks` は何かを実行した場合に `true` を返し、その間はパークしない
+//------------------------------------------------------------------------------
+pub(crate) fn block_on_with<F: Future>( future: F, mut run_tasks: impl FnMut() -> bool )
+    -> F::Output
+{
+    let mut future = pin!(future);
+    let thread_waker = Arc::new(ThreadWaker
+    {
+        thread: std::thread::current(),
+        woken: AtomicBool::new(true),
+    });
+    let waker = Waker::from(thread_waker.clone());
+    let mut context = Context::from_waker(&waker);
+
+    loop
+    {
+        if thread_waker.woken.swap(false, Ordering::AcqRel)
+        {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context)
+            {
+                return output;
+            }
+        }
+
+        //  起床されるまでパーク
+        //  アンパークが先に呼び出されていた場合はすぐに戻る
+        if !run_tasks() && !thread_waker.woken.load(Ordering::Acquire)
+        {
+            std::thread::park();
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  現在のスレッドでFutureを完了まで実行して、結果を返す
+//------------------------------------------------------------------------------
+pub fn block_on<F: Future>( future: F ) -> F::Output
+{
+    block_on_with(future, || false)
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::block_on;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{ Context, Poll };
+    use core::time::Duration;
+
+    //--------------------------------------------------------------------------
+    //  別スレッドから起床されるFuture
+    //--------------------------------------------------------------------------
+    struct WakeFromThread
+    {
+        woken: bool,
+    }
+
+    impl Future for WakeFromThread
+    {
+        type Output = u32;
+
+        fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<u32>
+        {
+            if self.woken
+            {
+                return Poll::Ready(7);
+            }
+            self.woken = true;
+            let waker = cx.waker().clone();
+            std::thread::spawn(move ||
+            {
+                std::thread::sleep(Duration::from_millis(10));
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_block_on
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_block_on()
+    {
+        let value = block_on(async
+        {
+            let a = WakeFromThread { woken: false }.await;
+            let b = WakeFromThread { woken: false }.await;
+            a + b
+        });
+        assert_eq!(14, value);
+    }
+}
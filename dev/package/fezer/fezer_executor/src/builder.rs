@@ -0,0 +1,203 @@
+/*
+
+    Executorのビルダ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `Executor::new()` では指定できないExecutorの設定を行う。
+
+    ```rust
+    let executor = fezer_executor::Executor::builder("executor")
+        .flavor(fezer_executor::Flavor::MultiThread)
+        .num_threads(4)
+        .max_blocking_threads(64)
+        .blocking_keep_alive(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    ```
+
+    `spawn_blocking()` のジョブは、タスクを実行するスレッドプールとは別の伸縮す
+    るスレッドプールで実行される。ブロッキング用のスレッドはジョブがあるときに
+    だけ `max_blocking_threads` まで起動し、`blocking_keep_alive` の間ジョブが
+    なければ停止する。
+
+    `Flavor::CurrentThread` を指定した場合は `num_threads` は使用されず、タスク
+    は `block_on()` か `run()` を呼び出したスレッドで実行される。
+
+    `panic_handler` はタスクがパニックになったときに、パニックのペイロードを受
+    け取る。パニックは `JoinHandle` にも `JoinError::Panic` として渡される。
+
+*/
+
+use crate::executor::{ Executor, Scheduler };
+use crate::local_queue::LocalQueue;
+use crate::time::TimerDriver;
+
+use core::any::Any;
+use core::fmt::{ Debug, Formatter };
+use core::time::Duration;
+use std::sync::{ Arc, Condvar, Mutex };
+use fezer_threadpool::ThreadPool;
+use fezer_threadpool::error::NewThreadPoolError;
+
+//  タスクがパニックになったときに実行されるクロージャ
+pub(crate) type PanicHandlerFn = Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync + 'static>;
+
+//------------------------------------------------------------------------------
+//  タスクを実行するスレッドの種類
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flavor
+{
+    //  `block_on()` か `run()` を呼び出したスレッドで実行する
+    CurrentThread,
+
+    //  スレッドプールのワーカースレッドで実行する
+    #[default]
+    MultiThread,
+}
+
+//------------------------------------------------------------------------------
+//  ExecutorBuilder
+//------------------------------------------------------------------------------
+pub struct ExecutorBuilder
+{
+    //  Executorの名前
+    name: String,
+
+    //  タスクを実行するスレッドの種類
+    flavor: Flavor,
+
+    //  タスクを実行するスレッド数
+    num_threads: usize,
+
+    //  ブロッキング用のスレッド数の上限
+    max_blocking_threads: usize,
+
+    //  アイドル状態のブロッキング用のスレッドを停止するまでの時間
+    blocking_keep_alive: Duration,
+
+    //  タスクがパニックになったときに実行されるクロージャ
+    panic_handler: Option<PanicHandlerFn>,
+}
+
+impl ExecutorBuilder
+{
+    //--------------------------------------------------------------------------
+    //  新しいビルダを生成
+    //--------------------------------------------------------------------------
+    pub fn new( name: impl Into<String> ) -> Self
+    {
+        Self
+        {
+            name: name.into(),
+            flavor: Flavor::default(),
+            num_threads: 1,
+            max_blocking_threads: 512,
+            blocking_keep_alive: Duration::from_secs(10),
+            panic_handler: None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクを実行するスレッドの種類
+    //--------------------------------------------------------------------------
+    pub fn flavor( mut self, flavor: Flavor ) -> Self
+    {
+        self.flavor = flavor;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクを実行するスレッド数
+    //--------------------------------------------------------------------------
+    pub fn num_threads( mut self, num_threads: usize ) -> Self
+    {
+        self.num_threads = num_threads;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ブロッキング用のスレッド数の上限
+    //--------------------------------------------------------------------------
+    pub fn max_blocking_threads( mut self, max_blocking_threads: usize ) -> Self
+    {
+        self.max_blocking_threads = max_blocking_threads;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  アイドル状態のブロッキング用のスレッドを停止するまでの時間
+    //--------------------------------------------------------------------------
+    pub fn blocking_keep_alive( mut self, keep_alive: Duration ) -> Self
+    {
+        self.blocking_keep_alive = keep_alive;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクがパニックになったときに実行されるクロージャ
+    //  引数はパニックのペイロード
+    //--------------------------------------------------------------------------
+    pub fn panic_handler<F>( mut self, f: F ) -> Self
+    where
+        F: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  Executorを生成
+    //--------------------------------------------------------------------------
+    pub fn build( self ) -> Result<Arc<Executor>, NewThreadPoolError>
+    {
+        let scheduler = match self.flavor
+        {
+            Flavor::MultiThread => Scheduler::MultiThread
+            (
+                ThreadPool::builder(self.name.as_str()).size(self.num_threads).build()?
+            ),
+            Flavor::CurrentThread => Scheduler::CurrentThread(LocalQueue::new()),
+        };
+        let blocking_pool = ThreadPool::builder(format!("{}-blocking", self.name))
+            .size_range(0, self.max_blocking_threads)
+            .keep_alive(self.blocking_keep_alive)
+            .unbounded_queue()
+            .build()?;
+
+        Ok(Arc::new(Executor
+        {
+            scheduler,
+            blocking_pool,
+            panic_handler: self.panic_handler,
+            timer: TimerDriver::new(format!("{}-timer", self.name)),
+            num_tasks: Mutex::new(0),
+            all_done: Condvar::new(),
+        }))
+    }
+}
+
+impl Debug for ExecutorBuilder
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!
+        (
+            f,
+            "ExecutorBuilder{{{:?}, flavor={:?}, num_threads={}, max_blocking_threads={}, \
+             blocking_keep_alive={:?}, panic_handler={}}}",
+            self.name,
+            self.flavor,
+            self.num_threads,
+            self.max_blocking_threads,
+            self.blocking_keep_alive,
+            self.panic_handler.is_some()
+        )
+    }
+}
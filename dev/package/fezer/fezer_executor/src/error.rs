@@ -0,0 +1,107 @@
+/*
+
+    Executor用のエラー定義
+
+*/
+
+use core::any::Any;
+use core::fmt::{ Display, Formatter };
+use std::error::Error;
+
+//------------------------------------------------------------------------------
+//  パニックのペイロードからメッセージを取得
+//------------------------------------------------------------------------------
+#[doc(hidden)]
+pub fn panic_message( payload: &(dyn Any + Send) ) -> Option<&str>
+{
+    if let Some(s) = payload.downcast_ref::<&'static str>()
+    {
+        Some(s)
+    }
+    else
+    {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+//------------------------------------------------------------------------------
+//  タスクの結果を待つときのエラー
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum JoinError
+{
+    //  タスクがパニックになった場合
+    //  `std::panic::catch_unwind()` のペイロード
+    Panic(Box<dyn Any + Send + 'static>),
+
+    //  タスクが完了する前に中断された場合
+    Cancelled,
+}
+
+impl JoinError
+{
+    //--------------------------------------------------------------------------
+    //  タスクがパニックになったかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_panic( &self ) -> bool
+    {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクが中断されたかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_cancelled( &self ) -> bool
+    {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    //--------------------------------------------------------------------------
+    //  パニックのペイロードを取得
+    //  ※ パニック以外のエラーで呼び出すとpanic
+    //--------------------------------------------------------------------------
+    pub fn into_panic( self ) -> Box<dyn Any + Send + 'static>
+    {
+        match self
+        {
+            JoinError::Panic(payload) => payload,
+            e => panic!("JoinError::into_panic() called on {:?}", e),
+        }
+    }
+}
+
+impl Display for JoinError
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            JoinError::Panic(payload) =>
+            {
+                match panic_message(payload.as_ref())
+                {
+                    Some(message) => write!(f, "task panicked: {}", message),
+                    None => write!(f, "task panicked"),
+                }
+            },
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+impl PartialEq for JoinError
+{
+    //--------------------------------------------------------------------------
+    //  eq
+    //  パニックのペイロードは比較できないので、パニック同士は等しくない
+    //--------------------------------------------------------------------------
+    fn eq( &self, other: &Self ) -> bool
+    {
+        matches!((self, other), (JoinError::Cancelled, JoinError::Cancelled))
+    }
+}
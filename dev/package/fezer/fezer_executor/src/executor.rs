@@ -0,0 +1,677 @@
+/*
+
+    Executor
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    スレッドプールとタスクの生存数を管理する。
+
+    Executorは `Arc` で共有され、タスクは `Weak<Executor>` を保持する。タスクの
+    ポーリング中は実行中のExecutorがスレッドローカルに設定されるので、タスク内
+    部からは `spawn()` で同じExecutorに新しいタスクを生成することができる。
+
+    ブロッキングするファイルやデータベースのAPIは `spawn_blocking()` でブロッキ
+    ング用のスレッドプールに渡し、完了を `.await` で待つ。タスクを実行するワー
+    カースレッドはブロッキングされない。
+
+    `sleep()` と `sleep_until()` の期限はExecutorのタイマースレッドが管理する。
+
+    # フレーバー
+
+    - `Flavor::MultiThread` はタスクをスレッドプールのワーカースレッドで実行す
+      る
+    - `Flavor::CurrentThread` はタスクを `block_on()` か `run()` を呼び出したス
+      レッドで実行する。タスクは1つのスレッドで決まった順序でポーリングされる
+
+    `block_on()` はどちらのフレーバーでも、渡されたFutureを呼び出し元のスレッド
+    でポーリングし、その内部から `spawn()` でタスクを生成できる。
+
+*/
+
+use crate::block_on::block_on_with;
+use crate::builder::{ ExecutorBuilder, PanicHandlerFn };
+use crate::join_handle::{ Joinable, JoinHandle, JoinState };
+use crate::local_queue::LocalQueue;
+use crate::task::Task;
+use crate::time::{ Sleep, TimerDriver };
+
+use core::future::Future;
+use core::time::Duration;
+use std::cell::RefCell;
+use std::panic::resume_unwind;
+use std::sync::{ Arc, Condvar, Mutex, Weak };
+use std::time::Instant;
+use fezer_threadpool::ThreadPool;
+use fezer_threadpool::error::{ JoinError as BlockingJoinError, NewThreadPoolError };
+
+//  `sleep()` に表せない長さを渡したときに待つ時間（約30年）
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+thread_local!
+{
+    //  現在のスレッドでタスクを実行しているExecutor
+    static EXECUTOR: RefCell<Weak<Executor>> = const { RefCell::new(Weak::new()) };
+}
+
+//------------------------------------------------------------------------------
+//  タスクのスケジューラ
+//------------------------------------------------------------------------------
+pub(crate) enum Scheduler
+{
+    //  スレッドプールのワーカースレッドで実行する
+    MultiThread(ThreadPool),
+
+    //  `block_on()` か `run()` を呼び出したスレッドで実行する
+    CurrentThread(LocalQueue),
+}
+
+//------------------------------------------------------------------------------
+//  Executor
+//------------------------------------------------------------------------------
+pub struct Executor
+{
+    //  タスクのスケジューラ
+    pub(crate) scheduler: Scheduler,
+
+    //  ブロッキングするジョブを実行するスレッドプール
+    pub(crate) blocking_pool: ThreadPool,
+
+    //  タスクがパニックになったときに実行されるクロージャ
+    pub(crate) panic_handler: Option<PanicHandlerFn>,
+
+    //  `Sleep` の期限を管理するタイマー
+    pub(crate) timer: TimerDriver,
+
+    //  完了していないタスクの数
+    pub(crate) num_tasks: Mutex<usize>,
+
+    //  すべてのタスクが完了したことを通知する
+    pub(crate) all_done: Condvar,
+}
+
+impl Executor
+{
+    //--------------------------------------------------------------------------
+    //  Executorを生成
+    //--------------------------------------------------------------------------
+    pub fn new( name: &'static str, num_threads: usize )
+        -> Result<Arc<Self>, NewThreadPoolError>
+    {
+        ExecutorBuilder::new(name).num_threads(num_threads).build()
+    }
+
+    //--------------------------------------------------------------------------
+    //  ビルダを生成
+    //--------------------------------------------------------------------------
+    pub fn builder( name: impl Into<String> ) -> ExecutorBuilder
+    {
+        ExecutorBuilder::new(name)
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクを生成して、結果を受け取るハンドルを返す
+    //--------------------------------------------------------------------------
+    pub fn spawn<T, F>( self: &Arc<Self>, future: F ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        *self.num_tasks.lock().unwrap() += 1;
+        let state = Arc::new(JoinState::new());
+        let future = Joinable::new(future, state.clone(), self.panic_handler.clone());
+        let task = Arc::new(Task::new(future, Arc::downgrade(self)));
+        self.schedule(task.clone());
+        JoinHandle::new(task, state)
+    }
+
+    //--------------------------------------------------------------------------
+    //  ブロッキングするジョブをブロッキング用のスレッドプールで実行
+    //  ジョブがパニックになった場合は `.await` したタスクでパニックを再開する
+    //--------------------------------------------------------------------------
+    pub fn spawn_blocking<T, F>( &self, f: F ) -> impl Future<Output = T> + Send + 'static
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let handle = self.blocking_pool.spawn(f);
+        async move
+        {
+            match handle.await
+            {
+                Ok(value) => value,
+                Err(BlockingJoinError::Panic(payload)) => resume_unwind(payload),
+                Err(e) => panic!("fezer_executor::spawn_blocking() job failed: {}", e),
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  指定した時間が経過すると完了するFutureを返す
+    //  期限が `Instant` で表せない場合は十分に先の時刻まで待つ
+    //--------------------------------------------------------------------------
+    pub fn sleep( &self, duration: Duration ) -> Sleep
+    {
+        let now = Instant::now();
+        let deadline = now.checked_add(duration)
+            .unwrap_or_else(|| now + FAR_FUTURE);
+        self.sleep_until(deadline)
+    }
+
+    //--------------------------------------------------------------------------
+    //  指定した時刻になると完了するFutureを返す
+    //--------------------------------------------------------------------------
+    pub fn sleep_until( &self, deadline: Instant ) -> Sleep
+    {
+        Sleep::new(deadline, self.timer.shared().clone())
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドでFutureを完了まで実行して、結果を返す
+    //  Futureの内部からは `spawn()` でこのExecutorにタスクを生成できる
+    //--------------------------------------------------------------------------
+    pub fn block_on<F: Future>( self: &Arc<Self>, future: F ) -> F::Output
+    {
+        Executor::enter(&Arc::downgrade(self), || match &self.scheduler
+        {
+            Scheduler::MultiThread(_) => block_on_with(future, || false),
+            Scheduler::CurrentThread(local) =>
+            {
+                local.set_driver();
+                block_on_with(future, || local.run_pending())
+            },
+        })
+    }
+
+    //--------------------------------------------------------------------------
+    //  すべてのタスクが完了するまで待ち、スレッドプールを停止する
+    //--------------------------------------------------------------------------
+    pub fn run( self: Arc<Self> )
+    {
+        self.run_until(None);
+    }
+
+    //--------------------------------------------------------------------------
+    //  すべてのタスクが完了するか、タイムアウトするまで待つ
+    //  タイムアウトまでに完了しなかったタスクの数を返す。すべてのタスクが完了
+    //  した場合は `run()` と同じくスレッドプールを停止する
+    //--------------------------------------------------------------------------
+    pub fn run_timeout( self: Arc<Self>, timeout: Duration ) -> usize
+    {
+        let now = Instant::now();
+        let deadline = now.checked_add(timeout)
+            .unwrap_or_else(|| now + FAR_FUTURE);
+        self.run_until(Some(deadline))
+    }
+
+    //--------------------------------------------------------------------------
+    //  すべてのタスクが完了するか、期限が来るまで待つ
+    //  完了しなかったタスクの数を返す
+    //--------------------------------------------------------------------------
+    fn run_until( self: Arc<Self>, deadline: Option<Instant> ) -> usize
+    {
+        //  期限までの残り時間
+        //  期限を過ぎていれば `None`
+        let remaining = |deadline: Instant|
+        {
+            Some(deadline.saturating_duration_since(Instant::now())).filter(|d| !d.is_zero())
+        };
+
+        let num_remaining = match &self.scheduler
+        {
+            Scheduler::MultiThread(_) =>
+            {
+                let mut num_tasks = self.num_tasks.lock().unwrap();
+                while *num_tasks > 0
+                {
+                    num_tasks = match deadline
+                    {
+                        Some(deadline) => match remaining(deadline)
+                        {
+                            Some(timeout) =>
+                            {
+                                self.all_done.wait_timeout(num_tasks, timeout).unwrap().0
+                            },
+                            None => break,
+                        },
+                        None => self.all_done.wait(num_tasks).unwrap(),
+                    };
+                }
+                *num_tasks
+            },
+
+            //  現在のスレッドでタスクを実行
+            Scheduler::CurrentThread(local) =>
+            {
+                local.set_driver();
+                Executor::enter(&Arc::downgrade(&self), || loop
+                {
+                    let num_tasks = *self.num_tasks.lock().unwrap();
+                    if num_tasks < 1
+                    {
+                        break 0;
+                    }
+                    let timeout = match deadline
+                    {
+                        Some(deadline) => match remaining(deadline)
+                        {
+                            Some(timeout) => Some(timeout),
+                            None => break num_tasks,
+                        },
+                        None => None,
+                    };
+                    if !local.run_pending()
+                    {
+                        match timeout
+                        {
+                            Some(timeout) => std::thread::park_timeout(timeout),
+                            None => std::thread::park(),
+                        }
+                    }
+                })
+            },
+        };
+
+        //  すべてのタスクが完了し、他に参照が残っていなければ、スレッドの停止を
+        //  待つ
+        if num_remaining > 0
+        {
+            return num_remaining;
+        }
+        if let Ok(executor) = Arc::try_unwrap(self)
+        {
+            if let Scheduler::MultiThread(pool) = executor.scheduler
+            {
+                pool.join();
+            }
+            executor.blocking_pool.join();
+        }
+        0
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクをスケジュール
+    //--------------------------------------------------------------------------
+    pub(crate) fn schedule( &self, task: Arc<Task> )
+    {
+        match &self.scheduler
+        {
+            Scheduler::MultiThread(pool) => pool.schedule(move || task.poll()),
+            Scheduler::CurrentThread(local) => local.push(task),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクの完了を記録
+    //--------------------------------------------------------------------------
+    pub(crate) fn task_completed( &self )
+    {
+        let mut num_tasks = self.num_tasks.lock().unwrap();
+        *num_tasks -= 1;
+        if *num_tasks == 0
+        {
+            self.all_done.notify_all();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドにExecutorを設定してクロージャを実行
+    //--------------------------------------------------------------------------
+    pub(crate) fn enter<R>( executor: &Weak<Executor>, f: impl FnOnce() -> R ) -> R
+    {
+        //  パニック時にも元のExecutorに戻す
+        struct Reset(Weak<Executor>);
+        impl Drop for Reset
+        {
+            fn drop( &mut self )
+            {
+                let prev = std::mem::take(&mut self.0);
+                EXECUTOR.with(|cell| cell.replace(prev));
+            }
+        }
+
+        let prev = EXECUTOR.with(|cell| cell.replace(executor.clone()));
+        let _reset = Reset(prev);
+        f()
+    }
+}
+
+//------------------------------------------------------------------------------
+//  現在のタスクと同じExecutorで実行される新しいタスクを生成し、結果を受け取る
+//  ハンドルを返す
+//
+//  ※ Executorのタスクの外部から呼び出されるとpanic
+//------------------------------------------------------------------------------
+pub fn spawn<T, F>( future: F ) -> JoinHandle<T>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let executor = EXECUTOR
+        .with(|cell| cell.borrow().upgrade())
+        .expect("fezer_executor::spawn() called from outside an executor");
+    executor.spawn(future)
+}
+
+//------------------------------------------------------------------------------
+//  現在のタスクと同じExecutorのブロッキング用のスレッドプールでジョブを実行し、
+//  完了したときに呼び出し元のタスクを起床する
+//
+//  ※ Executorのタスクの外部から呼び出されるとpanic
+//------------------------------------------------------------------------------
+pub fn spawn_blocking<T, F>( f: F ) -> impl Future<Output = T> + Send + 'static
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let executor = EXECUTOR
+        .with(|cell| cell.borrow().upgrade())
+        .expect("fezer_executor::spawn_blocking() called from outside an executor");
+    executor.spawn_blocking(f)
+}
+
+//------------------------------------------------------------------------------
+//  現在のタスクと同じExecutorのタイマーで、指定した時間が経過すると完了する
+//  Futureを返す
+//
+//  ※ Executorのタスクの外部から呼び出されるとpanic
+//------------------------------------------------------------------------------
+pub fn sleep( duration: Duration ) -> Sleep
+{
+    let executor = EXECUTOR
+        .with(|cell| cell.borrow().upgrade())
+        .expect("fezer_executor::sleep() called from outside an executor");
+    executor.sleep(duration)
+}
+
+//------------------------------------------------------------------------------
+//  現在のタスクと同じExecutorのタイマーで、指定した時刻になると完了するFuture
+//  を返す
+//
+//  ※ Executorのタスクの外部から呼び出されるとpanic
+//------------------------------------------------------------------------------
+pub fn sleep_until( deadline: Instant ) -> Sleep
+{
+    let executor = EXECUTOR
+        .with(|cell| cell.borrow().upgrade())
+        .expect("fezer_executor::sleep_until() called from outside an executor");
+    executor.sleep_until(deadline)
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::{ spawn, spawn_blocking, Executor };
+    use crate::builder::Flavor;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{ Context, Poll };
+    use core::time::Duration;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::{ Arc, Barrier };
+
+    //--------------------------------------------------------------------------
+    //  別スレッドから起床されるFuture
+    //--------------------------------------------------------------------------
+    struct WakeFromThread
+    {
+        woken: bool,
+    }
+
+    impl Future for WakeFromThread
+    {
+        type Output = ();
+
+        fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+        {
+            if self.woken
+            {
+                return Poll::Ready(());
+            }
+            self.woken = true;
+            let waker = cx.waker().clone();
+            std::thread::spawn(move ||
+            {
+                std::thread::sleep(Duration::from_millis(10));
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_and_run
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_and_run()
+    {
+        let executor = Executor::new("test", 4).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100
+        {
+            let counter_clone = counter.clone();
+            executor.spawn(async move
+            {
+                WakeFromThread { woken: false }.await;
+                counter_clone.fetch_add(1, Ordering::AcqRel);
+            });
+        }
+        executor.run();
+        assert_eq!(100, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_from_task
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_from_task()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        executor.spawn(async move
+        {
+            for _ in 0..10
+            {
+                let counter_clone = counter_clone.clone();
+                spawn(async move
+                {
+                    counter_clone.fetch_add(1, Ordering::AcqRel);
+                });
+            }
+        });
+        executor.run();
+        assert_eq!(10, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_panicking_task
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_panicking_task()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        executor.spawn(async { panic!("task panicked") });
+        let counter_clone = counter.clone();
+        executor.spawn(async move
+        {
+            counter_clone.fetch_add(1, Ordering::AcqRel);
+        });
+        executor.run();
+        assert_eq!(1, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_panic_handler
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_panic_handler()
+    {
+        let messages = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let messages_clone = messages.clone();
+        let executor = Executor::builder("test")
+            .panic_handler(move |payload|
+            {
+                let message = payload.downcast_ref::<&str>().unwrap().to_string();
+                messages_clone.lock().unwrap().push(message);
+            })
+            .build()
+            .unwrap();
+        executor.spawn(async { panic!("task panicked") });
+        executor.spawn(async {});
+        executor.run();
+        assert_eq!(vec!["task panicked".to_string()], *messages.lock().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_blocking
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_blocking()
+    {
+        let executor = Executor::builder("test")
+            .num_threads(1)
+            .max_blocking_threads(4)
+            .blocking_keep_alive(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        //  ブロッキングするジョブが直列に実行されるとバリアで停止する
+        let barrier = Arc::new(Barrier::new(4));
+        for n in 0..4
+        {
+            let counter_clone = counter.clone();
+            let barrier_clone = barrier.clone();
+            executor.spawn(async move
+            {
+                //  ブロッキング中もワーカースレッドは他のタスクを実行できる
+                let name = spawn_blocking(move ||
+                {
+                    barrier_clone.wait();
+                    std::thread::current().name().unwrap().to_string()
+                })
+                .await;
+                assert!(name.starts_with("test-blocking"));
+                counter_clone.fetch_add(n, Ordering::AcqRel);
+            });
+        }
+        executor.run();
+        assert_eq!(6, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_blocking_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_blocking_panic()
+    {
+        let executor = Executor::new("test", 1).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        executor.spawn(async move
+        {
+            let result = spawn_blocking(|| panic!("blocking job panicked"));
+            let _: () = result.await;
+            counter_clone.fetch_add(1, Ordering::AcqRel);
+        });
+        executor.run();
+        assert_eq!(0, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_outside_executor
+    //--------------------------------------------------------------------------
+    #[test]
+    #[should_panic(expected = "called from outside an executor")]
+    fn test_spawn_outside_executor()
+    {
+        spawn(async {});
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_block_on
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_block_on()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let value = executor.block_on(async
+        {
+            let handle = spawn(async
+            {
+                WakeFromThread { woken: false }.await;
+                std::thread::current().name().unwrap().to_string()
+            });
+            handle.await.unwrap()
+        });
+        assert!(value.starts_with("test"));
+        executor.run();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_current_thread
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_current_thread()
+    {
+        let executor = Executor::builder("test").flavor(Flavor::CurrentThread).build().unwrap();
+        let thread_id = std::thread::current().id();
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        //  すべてのタスクが呼び出し元のスレッドで実行される
+        let value = executor.block_on(async
+        {
+            let handles: Vec<_> = (0..10).map(|n| spawn(async move
+            {
+                WakeFromThread { woken: false }.await;
+                assert_eq!(thread_id, std::thread::current().id());
+                n
+            }))
+            .collect();
+            let mut sum = 0;
+            for handle in handles
+            {
+                sum += handle.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(45, value);
+
+        let counter_clone = counter.clone();
+        executor.spawn(async move
+        {
+            WakeFromThread { woken: false }.await;
+            assert_eq!(thread_id, std::thread::current().id());
+            counter_clone.fetch_add(1, Ordering::AcqRel);
+        });
+        executor.run();
+        assert_eq!(1, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_run_timeout
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_run_timeout()
+    {
+        for flavor in [Flavor::MultiThread, Flavor::CurrentThread]
+        {
+            //  完了したタスクを待って0を返す
+            let executor = Executor::builder("test").flavor(flavor).build().unwrap();
+            executor.spawn(WakeFromThread { woken: false });
+            assert_eq!(0, executor.run_timeout(Duration::from_secs(5)));
+
+            //  完了しないタスクの数を返す
+            let executor = Executor::builder("test").flavor(flavor).build().unwrap();
+            executor.spawn(core::future::pending::<()>());
+            executor.spawn(WakeFromThread { woken: false });
+            assert_eq!(1, executor.run_timeout(Duration::from_millis(50)));
+        }
+    }
+}
@@ -0,0 +1,360 @@
+/*
+
+    タスクの結果を受け取るハンドル
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `spawn()` で生成したタスクの戻り値を受け取る。
+
+    ```rust
+    let handle = fezer_executor::spawn(async { compute().await });
+    match handle.await
+    {
+        Ok(value) => use_value(value),
+        Err(e) if e.is_cancelled() => {},
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+    ```
+
+    - `.await` でタスクの完了を待ち、`Result<T, JoinError>` を受け取る
+    - `abort()` でタスクを中断する。中断されたタスクのFutureは次にポーリングさ
+      れる代わりに破棄され、ハンドルは `JoinError::Cancelled` を返す
+    - `is_finished()` でタスクが完了したかどうかを確認する
+
+    タスクがパニックになった場合は、ペイロードを含む `JoinError::Panic` が返る。
+    ハンドルをドロップしてもタスクは中断されず、完了するまで実行される。
+
+*/
+
+use crate::builder::PanicHandlerFn;
+use crate::error::JoinError;
+use crate::task::Task;
+
+use core::fmt::{ Debug, Formatter };
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use core::task::{ Context, Poll, Waker };
+use std::any::type_name;
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::sync::{ Arc, Mutex };
+
+//------------------------------------------------------------------------------
+//  結果の受け渡し場所
+//------------------------------------------------------------------------------
+struct JoinSlot<T>
+{
+    //  タスクの結果
+    result: Option<Result<T, JoinError>>,
+
+    //  結果を待っているタスクのWaker
+    waker: Option<Waker>,
+}
+
+//------------------------------------------------------------------------------
+//  タスクとハンドルで共有する結果
+//------------------------------------------------------------------------------
+pub(crate) struct JoinState<T>
+{
+    //  結果の受け渡し場所
+    slot: Mutex<JoinSlot<T>>,
+
+    //  タスクが完了したかどうか
+    finished: AtomicBool,
+}
+
+impl<T> JoinState<T>
+{
+    //--------------------------------------------------------------------------
+    //  新しい共有状態を生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            slot: Mutex::new(JoinSlot { result: None, waker: None }),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  結果を設定して、待っているタスクを起床
+    //  既に結果が設定されている場合は何もしない
+    //--------------------------------------------------------------------------
+    fn complete( &self, result: Result<T, JoinError> )
+    {
+        let mut slot = self.slot.lock().unwrap();
+        if self.finished.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+        slot.result = Some(result);
+        let waker = slot.waker.take();
+        drop(slot);
+
+        if let Some(waker) = waker
+        {
+            waker.wake();
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  結果を共有状態に書き込むFuture
+//  完了する前にドロップされた場合は `JoinError::Cancelled` を書き込む
+//------------------------------------------------------------------------------
+pub(crate) struct Joinable<F: Future>
+{
+    //  タスクのFuture
+    future: Pin<Box<F>>,
+
+    //  ハンドルと共有する結果
+    state: Arc<JoinState<F::Output>>,
+
+    //  パニックになったときに実行されるクロージャ
+    panic_handler: Option<PanicHandlerFn>,
+}
+
+impl<F: Future> Joinable<F>
+{
+    //--------------------------------------------------------------------------
+    //  新しいFutureを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new(
+        future: F,
+        state: Arc<JoinState<F::Output>>,
+        panic_handler: Option<PanicHandlerFn>,
+    ) -> Self
+    {
+        Self { future: Box::pin(future), state, panic_handler }
+    }
+}
+
+impl<F: Future> Future for Joinable<F>
+{
+    type Output = ();
+
+    //--------------------------------------------------------------------------
+    //  poll
+    //  パニックは捕捉してハンドルに渡す
+    //--------------------------------------------------------------------------
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        let this = self.get_mut();
+        let result = match catch_unwind(AssertUnwindSafe(|| this.future.as_mut().poll(cx)))
+        {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(value)) => Ok(value),
+            Err(payload) =>
+            {
+                if let Some(panic_handler) = &this.panic_handler
+                {
+                    panic_handler(payload.as_ref());
+                }
+                Err(JoinError::Panic(payload))
+            },
+        };
+        this.state.complete(result);
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Drop for Joinable<F>
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.state.complete(Err(JoinError::Cancelled));
+    }
+}
+
+//------------------------------------------------------------------------------
+//  JoinHandle
+//------------------------------------------------------------------------------
+pub struct JoinHandle<T>
+{
+    //  結果を受け取るタスク
+    task: Arc<Task>,
+
+    //  タスクと共有する結果
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  新しいハンドルを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( task: Arc<Task>, state: Arc<JoinState<T>> ) -> Self
+    {
+        Self { task, state }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクを中断
+    //  完了済みのタスクに対しては何もしない
+    //--------------------------------------------------------------------------
+    pub fn abort( &self )
+    {
+        self.task.abort();
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクが完了したかどうか
+    //  中断されたタスクとパニックになったタスクも完了として扱う
+    //--------------------------------------------------------------------------
+    pub fn is_finished( &self ) -> bool
+    {
+        self.state.finished.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Future for JoinHandle<T>
+{
+    type Output = Result<T, JoinError>;
+
+    //--------------------------------------------------------------------------
+    //  poll
+    //--------------------------------------------------------------------------
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
+    {
+        let mut slot = self.state.slot.lock().unwrap();
+        if let Some(result) = slot.result.take()
+        {
+            return Poll::Ready(result);
+        }
+        assert!
+        (
+            !self.state.finished.load(Ordering::Acquire),
+            "JoinHandle polled after completion"
+        );
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Debug for JoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "JoinHandle<{}>{{finished={}}}", type_name::<T>(), self.is_finished())
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::error::JoinError;
+    use crate::executor::{ spawn, Executor };
+    use core::future::{ pending, Future };
+    use core::pin::Pin;
+    use core::task::{ Context, Poll };
+    use core::time::Duration;
+    use std::sync::mpsc::channel;
+
+    //--------------------------------------------------------------------------
+    //  一度だけPendingを返すFuture
+    //--------------------------------------------------------------------------
+    struct YieldNow
+    {
+        yielded: bool,
+    }
+
+    impl Future for YieldNow
+    {
+        type Output = ();
+
+        fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+        {
+            if self.yielded
+            {
+                return Poll::Ready(());
+            }
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_join_handle
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_join_handle()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let (sender, receiver) = channel();
+        let handle = executor.spawn(async move
+        {
+            let child = spawn(async
+            {
+                YieldNow { yielded: false }.await;
+                21
+            });
+            let value = child.await.unwrap() * 2;
+            let panicked = spawn(async { panic!("child panicked") }).await;
+            sender.send((value, panicked)).unwrap();
+            "done"
+        });
+
+        let (value, panicked) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(42, value);
+        assert_eq!("child panicked", *panicked.unwrap_err().into_panic().downcast::<&str>().unwrap());
+        executor.spawn(async move
+        {
+            let result = handle.await;
+            assert_eq!(Ok("done"), result);
+        });
+        executor.run();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_abort
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_abort()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let (sender, receiver) = channel();
+        executor.spawn(async move
+        {
+            let handle = spawn(pending::<()>());
+            assert!(!handle.is_finished());
+            handle.abort();
+            sender.send(handle.await).unwrap();
+        });
+        assert_eq!(Err(JoinError::Cancelled), receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        //  中断されたタスクも完了として数えられる
+        executor.run();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_detach
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_detach()
+    {
+        let executor = Executor::new("test", 2).unwrap();
+        let (sender, receiver) = channel();
+        drop(executor.spawn(async move
+        {
+            YieldNow { yielded: false }.await;
+            sender.send(()).unwrap();
+        }));
+
+        //  ハンドルをドロップしてもタスクは完了まで実行される
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        executor.run();
+    }
+}
@@ -1,11 +1,61 @@
-//  use core::cell::Cell;
-//  use core::future::Future;
-//  use core::pin::Pin;
-//  use core::task::Poll;
-//  use std::sync::mpsc::SyncSender;
-//  use std::sync::{ Arc, Mutex, Weak };
-
-thread_local!
-{
-//    static EXECUTOR: Cell<Weak<Executor>> = Cell::new(Weak::new());
-}
+/*
+
+    非同期ランタイムのExecutor
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    fezer_threadpoolのスレッドプール上でタスクを実行するマルチスレッドの
+    Executor。
+
+    - タスクは `Send` であり、いずれかのワーカースレッドでポーリングされる
+    - `Arc` ベースのWakerによって、起床したタスクはスレッドプールに再スケジュー
+      ルされる
+    - タスクの状態はアトミックに遷移し、何度起床されても同時に2回以上キューに
+      追加されることはない
+    - パニックになったワーカースレッドの再起動はスレッドプールに任せる
+    - `spawn_blocking()` でブロッキングする処理を専用のスレッドプールで実行し、
+      完了を `.await` で待つ
+    - `spawn()` はタスクの結果を受け取る `JoinHandle` を返す。ハンドルからタス
+      クを中断でき、ドロップしてもタスクは中断されない
+    - `Flavor::CurrentThread` で、スレッドプールを使わずに呼び出し元のスレッド
+      だけでタスクを実行する
+    - `block_on()` と `Executor::block_on()` で、Futureを現在のスレッドで完了ま
+      で実行する
+    - `sleep()` と `sleep_until()` の期限をExecutorごとに1つのタイマースレッド
+      と階層型タイマーホイールで管理する。登録と取り消しはO(1)で、ドロップした
+      Sleepは登録が取り消される
+
+    # 使用例
+
+    ```rust
+    let executor = fezer_executor::Executor::new("executor", 4).unwrap();
+    executor.spawn(async
+    {
+        fezer_executor::spawn(async { println!("child") });
+        println!("parent");
+    });
+    executor.run();
+
+    let value = fezer_executor::Executor::new("executor", 4)
+        .unwrap()
+        .block_on(async { fezer_executor::spawn(async { 1 + 1 }).await.unwrap() });
+    ```
+
+*/
+
+mod block_on;
+mod builder;
+mod executor;
+mod join_handle;
+mod local_queue;
+mod task;
+mod time;
+pub mod error;
+
+pub use block_on::block_on;
+pub use builder::{ ExecutorBuilder, Flavor };
+pub use executor::{ sleep, sleep_until, spawn, spawn_blocking, Executor };
+pub use join_handle::JoinHandle;
+pub use time::Sleep;
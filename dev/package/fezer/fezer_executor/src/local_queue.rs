@@ -0,0 +1,90 @@
+/*
+
+    シングルスレッドのExecutorのタスクキュー
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `Flavor::CurrentThread` のExecutorは、スレッドプールを持たずに
+    `block_on()` か `run()` を呼び出したスレッドでタスクを実行する。
+
+    起床したタスクはこのキューに追加され、タスクを実行しているスレッド（ドライ
+    バ）がアンパークされる。ドライバはキューのタスクを順に取り出してポーリング
+    し、キューが空になるとパークする。タスクは常に1つのスレッドで追加された順に
+    ポーリングされる。
+
+*/
+
+use crate::task::Task;
+
+use std::collections::VecDeque;
+use std::sync::{ Arc, Mutex };
+use std::thread::Thread;
+
+//------------------------------------------------------------------------------
+//  LocalQueue
+//------------------------------------------------------------------------------
+pub(crate) struct LocalQueue
+{
+    //  ポーリングを待っているタスク
+    tasks: Mutex<VecDeque<Arc<Task>>>,
+
+    //  タスクを実行しているスレッド
+    driver: Mutex<Option<Thread>>,
+}
+
+impl LocalQueue
+{
+    //--------------------------------------------------------------------------
+    //  新しいキューを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            tasks: Mutex::new(VecDeque::new()),
+            driver: Mutex::new(None),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドをドライバに設定
+    //--------------------------------------------------------------------------
+    pub(crate) fn set_driver( &self )
+    {
+        *self.driver.lock().unwrap() = Some(std::thread::current());
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクを追加して、ドライバをアンパーク
+    //--------------------------------------------------------------------------
+    pub(crate) fn push( &self, task: Arc<Task> )
+    {
+        self.tasks.lock().unwrap().push_back(task);
+        if let Some(driver) = self.driver.lock().unwrap().as_ref()
+        {
+            driver.unpark();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューに溜まっているタスクをポーリング
+    //  ポーリング中に追加されたタスクは次の呼び出しで実行する
+    //  タスクを1つ以上ポーリングした場合は `true` を返す
+    //--------------------------------------------------------------------------
+    pub(crate) fn run_pending( &self ) -> bool
+    {
+        let num_tasks = self.tasks.lock().unwrap().len();
+        for _ in 0..num_tasks
+        {
+            let task = self.tasks.lock().unwrap().pop_front();
+            match task
+            {
+                Some(task) => task.poll(),
+                None => break,
+            }
+        }
+        num_tasks > 0
+    }
+}
@@ -0,0 +1,311 @@
+/*
+
+    Executorによって実行されるタスク
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    タスクは `Arc` で共有され、Wakerもタスクへの `Arc` を保持する。Wakerは任意
+    のスレッドから呼び出すことができ、起床したタスクはExecutorのスケジューラに
+    再スケジュールされる。
+
+    # 状態遷移
+
+    タスクの状態はアトミック変数で管理し、1つのタスクが同時に2回以上キューに追
+    加されたり、並行してポーリングされたりすることはない。
+
+    - `IDLE` のタスクを起床すると `SCHEDULED` にしてキューに追加する
+    - `SCHEDULED` と `NOTIFIED` のタスクを起床しても何もしない
+    - ポーリング中 (`RUNNING`) に起床されたタスクは `NOTIFIED` にしておき、ポー
+      リングが `Pending` を返した後で再びキューに追加する
+    - 完了したタスクやパニックになったタスクは `COMPLETE` になり、起床しても何
+      もしない
+
+    中断されたタスクは起床されてキューに追加され、次のポーリングの代わりにFuture
+    を破棄して `COMPLETE` になる。
+
+*/
+
+use crate::executor::Executor;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{ AtomicBool, AtomicU8, Ordering };
+use core::task::{ Context, Poll };
+use std::sync::{ Arc, Mutex, Weak };
+use std::task::{ Wake, Waker };
+
+//  タスクが保持するFuture
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+//  起床を待っている
+const IDLE: u8 = 0;
+
+//  キューに追加されている
+const SCHEDULED: u8 = 1;
+
+//  ポーリング中
+const RUNNING: u8 = 2;
+
+//  ポーリング中に起床された
+const NOTIFIED: u8 = 3;
+
+//  完了した
+const COMPLETE: u8 = 4;
+
+//------------------------------------------------------------------------------
+//  Task
+//------------------------------------------------------------------------------
+pub(crate) struct Task
+{
+    //  Futureタスク
+    //  完了後は `None` になる
+    future: Mutex<Option<BoxFuture>>,
+
+    //  タスクの状態
+    state: AtomicU8,
+
+    //  中断が要求されたかどうか
+    aborted: AtomicBool,
+
+    //  タスクを実行するExecutor
+    executor: Weak<Executor>,
+}
+
+impl Task
+{
+    //--------------------------------------------------------------------------
+    //  新しいタスクを生成する
+    //  生成したタスクはキューに追加された状態から始まる
+    //--------------------------------------------------------------------------
+    pub(crate) fn new(
+        future: impl Future<Output = ()> + Send + 'static,
+        executor: Weak<Executor>,
+    ) -> Task
+    {
+        Task
+        {
+            future: Mutex::new(Some(Box::pin(future))),
+            state: AtomicU8::new(SCHEDULED),
+            aborted: AtomicBool::new(false),
+            executor,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスク（ステートマシン）を次の状態まで進める
+    //--------------------------------------------------------------------------
+    pub(crate) fn poll( self: Arc<Self> )
+    {
+        //  ポーリング中にパニックになった場合もタスクを完了扱いにする
+        struct Complete<'a>
+        {
+            task: &'a Task,
+            done: bool,
+        }
+        impl Drop for Complete<'_>
+        {
+            fn drop( &mut self )
+            {
+                if self.done
+                {
+                    self.task.state.store(COMPLETE, Ordering::Release);
+                    if let Some(executor) = self.task.executor.upgrade()
+                    {
+                        executor.task_completed();
+                    }
+                }
+            }
+        }
+
+        //  キューに追加されたタスクだけをポーリングする
+        if self.state
+            .compare_exchange(SCHEDULED, RUNNING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+
+        //  中断されたタスクはポーリングせずにFutureを破棄
+        let mut future_guard = self.future.lock().unwrap();
+        if self.aborted.load(Ordering::Acquire)
+        {
+            let future = future_guard.take();
+            drop(future_guard);
+            let _complete = Complete { task: &self, done: true };
+            drop(future);
+            return;
+        }
+
+        let future = match future_guard.as_mut()
+        {
+            Some(future) => future,
+            None => return,
+        };
+
+        let waker = Waker::from(self.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut complete = Complete { task: &self, done: true };
+
+        let poll = Executor::enter(&self.executor, || future.as_mut().poll(&mut context));
+        match poll
+        {
+            Poll::Ready(()) =>
+            {
+                *future_guard = None;
+                return;
+            },
+            Poll::Pending => complete.done = false,
+        }
+        drop(complete);
+        drop(future_guard);
+
+        //  ポーリング中に起床されていた場合は再びキューに追加
+        if self.state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            self.state.store(SCHEDULED, Ordering::Release);
+            self.schedule();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タスクの中断を要求
+    //  ポーリング中のタスクは、ポーリングが終わった後で中断される
+    //--------------------------------------------------------------------------
+    pub(crate) fn abort( self: &Arc<Self> )
+    {
+        self.aborted.store(true, Ordering::Release);
+        self.wake_by_ref();
+    }
+
+    //--------------------------------------------------------------------------
+    //  Executorのキューに追加
+    //  Executorが既に破棄されていた場合はタスクも破棄する
+    //--------------------------------------------------------------------------
+    fn schedule( self: Arc<Self> )
+    {
+        if let Some(executor) = self.executor.upgrade()
+        {
+            executor.schedule(self);
+        }
+    }
+}
+
+impl Wake for Task
+{
+    //--------------------------------------------------------------------------
+    //  wake
+    //--------------------------------------------------------------------------
+    fn wake( self: Arc<Self> )
+    {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop
+        {
+            let next = match state
+            {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+
+                //  既にキューに追加されているか、完了している場合は何もしない
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+
+        if state == IDLE
+        {
+            self.schedule();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  wake_by_ref
+    //--------------------------------------------------------------------------
+    fn wake_by_ref( self: &Arc<Self> )
+    {
+        self.clone().wake();
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::Task;
+    use crate::builder::Flavor;
+    use crate::executor::Executor;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{ Context, Poll };
+    use std::sync::Arc;
+    use std::task::Waker;
+
+    //--------------------------------------------------------------------------
+    //  ポーリング中に何度も自身を起床するFuture
+    //--------------------------------------------------------------------------
+    struct WakeManyTimes
+    {
+        num_polls: usize,
+    }
+
+    impl Future for WakeManyTimes
+    {
+        type Output = usize;
+
+        fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<usize>
+        {
+            self.num_polls += 1;
+            if self.num_polls > 1
+            {
+                return Poll::Ready(self.num_polls);
+            }
+
+            //  同じスレッドと別のスレッドから繰り返し起床する
+            for _ in 0..10
+            {
+                cx.waker().wake_by_ref();
+            }
+            let wakers: Vec<_> = (0..10).map(|_| cx.waker().clone()).collect();
+            std::thread::spawn(move || wakers.into_iter().for_each(|waker| waker.wake()))
+                .join()
+                .unwrap();
+            Poll::Pending
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_wake_schedules_once
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_wake_schedules_once()
+    {
+        //  キューに追加されたタスクは実行されるまでキューから参照されるので、タ
+        //  スクの参照数からスケジュールされた回数が分かる
+        let executor = Executor::builder("test").flavor(Flavor::CurrentThread).build().unwrap();
+        let future = async { WakeManyTimes { num_polls: 0 }.await; };
+        let task = Arc::new(Task::new(future, Arc::downgrade(&executor)));
+
+        //  ポーリング中に何度起床されても、ポーリング後に1回だけスケジュールさ
+        //  れる
+        task.clone().poll();
+        assert_eq!(2, Arc::strong_count(&task));
+
+        //  キューに追加されているタスクを起床してもスケジュールされない
+        let waker = Waker::from(task.clone());
+        for _ in 0..10
+        {
+            waker.wake_by_ref();
+        }
+        drop(waker);
+        assert_eq!(2, Arc::strong_count(&task));
+    }
+}
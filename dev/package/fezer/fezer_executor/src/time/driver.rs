@@ -0,0 +1,229 @@
+/*
+
+    タイマードライバ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    Executorごとに1つのタイマースレッドで、`Sleep` の期限を管理する。
+
+    - 期限は階層型タイマーホイールに登録される。登録と削除はO(1)
+    - タイマースレッドは最初の登録時に起動し、ホイールの次の期限まで待ってから
+      期限が来たエントリのWakerを呼び出す
+    - 現在待っている時刻より早い期限が登録されたときだけ、タイマースレッドを起
+      こす
+
+    Executorが破棄されるとタイマーは閉じられ、タイマースレッドは終了する。登録
+    されていたWakerはその時点で破棄される。
+
+*/
+
+use crate::time::wheel::Wheel;
+
+use core::task::Waker;
+use core::time::Duration;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::time::Instant;
+
+//------------------------------------------------------------------------------
+//  タイマーの状態
+//------------------------------------------------------------------------------
+struct TimerState
+{
+    //  期限を管理するホイール
+    wheel: Wheel,
+
+    //  タイマースレッドが次に起きるティック
+    next_wake: Option<u64>,
+
+    //  タイマースレッドを起動したかどうか
+    started: bool,
+
+    //  タイマーが閉じられたかどうか
+    closed: bool,
+}
+
+//------------------------------------------------------------------------------
+//  タイマースレッドと `Sleep` で共有する状態
+//------------------------------------------------------------------------------
+pub(crate) struct TimerShared
+{
+    //  タイマースレッドの名前
+    name: String,
+
+    //  ティック0の時刻
+    origin: Instant,
+
+    state: Mutex<TimerState>,
+
+    //  早い期限の登録と、タイマーが閉じられたことを通知する
+    condvar: Condvar,
+}
+
+impl TimerShared
+{
+    //--------------------------------------------------------------------------
+    //  期限を登録してキーを返す
+    //
+    //  ※ タイマーが閉じられている場合や、タイマースレッドを起動できなかった場
+    //     合はpanic
+    //--------------------------------------------------------------------------
+    pub(crate) fn register( self: &Arc<Self>, deadline: Instant, waker: Waker ) -> usize
+    {
+        //  期限より前に起床しないように切り上げる
+        let since_origin = deadline.saturating_duration_since(self.origin);
+        let mut when = since_origin.as_millis() as u64;
+        if since_origin > Duration::from_millis(when)
+        {
+            when += 1;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        assert!(!state.closed, "fezer_executor timer has been shut down");
+        let key = state.wheel.insert(when, waker);
+        if when > state.wheel.elapsed() && state.next_wake.is_none_or(|next| when < next)
+        {
+            state.next_wake = Some(when);
+            self.condvar.notify_one();
+        }
+        let start = !state.started;
+        state.started = true;
+        drop(state);
+
+        if start
+        {
+            let shared = self.clone();
+            std::thread::Builder::new()
+                .name(self.name.clone())
+                .spawn(move || shared.run())
+                .expect("failed to spawn the fezer_executor timer thread");
+        }
+        key
+    }
+
+    //--------------------------------------------------------------------------
+    //  期限が来たかどうか
+    //  期限が来ていなければ、起床するWakerを更新する
+    //
+    //  ※ タイマーが閉じられている場合はpanic
+    //--------------------------------------------------------------------------
+    pub(crate) fn poll( &self, key: usize, waker: &Waker ) -> bool
+    {
+        let mut state = self.state.lock().unwrap();
+        assert!(!state.closed, "fezer_executor timer has been shut down");
+        state.wheel.poll(key, waker)
+    }
+
+    //--------------------------------------------------------------------------
+    //  期限の登録を取り消す
+    //--------------------------------------------------------------------------
+    pub(crate) fn deregister( &self, key: usize )
+    {
+        let mut state = self.state.lock().unwrap();
+        if !state.closed
+        {
+            state.wheel.remove(key);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のティック
+    //--------------------------------------------------------------------------
+    fn now( &self ) -> u64
+    {
+        self.origin.elapsed().as_millis() as u64
+    }
+
+    //--------------------------------------------------------------------------
+    //  タイマースレッドの処理
+    //--------------------------------------------------------------------------
+    fn run( self: Arc<Self> )
+    {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed
+        {
+            //  Wakerはロックの外で呼び出す
+            let wakers = state.wheel.advance(self.now());
+            if !wakers.is_empty()
+            {
+                drop(state);
+                wakers.into_iter().for_each(Waker::wake);
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state.next_wake = state.wheel.next_deadline();
+            state = match state.next_wake
+            {
+                Some(next) =>
+                {
+                    let timeout = (self.origin + Duration::from_millis(next))
+                        .saturating_duration_since(Instant::now());
+                    self.condvar.wait_timeout(state, timeout).unwrap().0
+                },
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  TimerDriver
+//------------------------------------------------------------------------------
+pub(crate) struct TimerDriver
+{
+    shared: Arc<TimerShared>,
+}
+
+impl TimerDriver
+{
+    //--------------------------------------------------------------------------
+    //  新しいタイマーを生成
+    //  タイマースレッドは最初の登録時に起動する
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( name: String ) -> Self
+    {
+        Self
+        {
+            shared: Arc::new(TimerShared
+            {
+                name,
+                origin: Instant::now(),
+                state: Mutex::new(TimerState
+                {
+                    wheel: Wheel::new(),
+                    next_wake: None,
+                    started: false,
+                    closed: false,
+                }),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  `Sleep` と共有する状態
+    //--------------------------------------------------------------------------
+    pub(crate) fn shared( &self ) -> &Arc<TimerShared>
+    {
+        &self.shared
+    }
+}
+
+impl Drop for TimerDriver
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //  タイマーを閉じて、登録されていたWakerをロックの外で破棄
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        let wheel = std::mem::replace(&mut state.wheel, Wheel::new());
+        self.shared.condvar.notify_all();
+        drop(state);
+        drop(wheel);
+    }
+}
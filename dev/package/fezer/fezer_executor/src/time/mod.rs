@@ -0,0 +1,30 @@
+/*
+
+    タイマー
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `sleep()` と `sleep_until()` で、指定した時間や時刻までタスクを待たせる。
+
+    ```rust
+    executor.spawn(async
+    {
+        fezer_executor::sleep(Duration::from_millis(100)).await;
+        fezer_executor::sleep_until(Instant::now() + Duration::from_secs(1)).await;
+    });
+    ```
+
+    期限はExecutorごとに1つのタイマースレッドが階層型タイマーホイールで管理す
+    る。Sleepごとにスレッドを起動することはなく、期限の登録と取り消しはO(1)で
+    行われる。
+
+*/
+
+mod driver;
+mod sleep;
+mod wheel;
+
+pub(crate) use driver::TimerDriver;
+pub use sleep::Sleep;
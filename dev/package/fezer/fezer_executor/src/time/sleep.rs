@@ -0,0 +1,244 @@
+/*
+
+    指定した時刻まで待つFuture
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `sleep()` と `sleep_until()` が返すFuture。最初のポーリングでExecutorのタ
+    イマーに期限を登録し、期限が来るとタイマースレッドから起床される。
+
+    期限が来る前にドロップされた場合は、タイマーから登録を取り消す。
+
+*/
+
+use crate::time::driver::TimerShared;
+
+use core::fmt::{ Debug, Formatter };
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use std::sync::Arc;
+use std::time::Instant;
+
+//------------------------------------------------------------------------------
+//  Sleep
+//------------------------------------------------------------------------------
+pub struct Sleep
+{
+    //  期限
+    deadline: Instant,
+
+    //  期限を登録するタイマー
+    timer: Arc<TimerShared>,
+
+    //  タイマーに登録したエントリのキー
+    //  最初のポーリングまでは `None`
+    key: Option<usize>,
+}
+
+impl Sleep
+{
+    //--------------------------------------------------------------------------
+    //  新しいFutureを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( deadline: Instant, timer: Arc<TimerShared> ) -> Self
+    {
+        Self { deadline, timer, key: None }
+    }
+
+    //--------------------------------------------------------------------------
+    //  期限
+    //--------------------------------------------------------------------------
+    pub fn deadline( &self ) -> Instant
+    {
+        self.deadline
+    }
+}
+
+impl Future for Sleep
+{
+    type Output = ();
+
+    //--------------------------------------------------------------------------
+    //  poll
+    //  ※ Executorが破棄された後でポーリングするとpanic
+    //--------------------------------------------------------------------------
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<()>
+    {
+        let key = match self.key
+        {
+            Some(key) => key,
+
+            //  既に期限が来ていればタイマーに登録しない
+            None if Instant::now() >= self.deadline => return Poll::Ready(()),
+            None =>
+            {
+                let key = self.timer.register(self.deadline, cx.waker().clone());
+                self.key = Some(key);
+                key
+            },
+        };
+
+        if self.timer.poll(key, cx.waker())
+        {
+            Poll::Ready(())
+        }
+        else
+        {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        if let Some(key) = self.key.take()
+        {
+            self.timer.deregister(key);
+        }
+    }
+}
+
+impl Debug for Sleep
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "Sleep{{deadline={:?}, registered={}}}", self.deadline, self.key.is_some())
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::builder::Flavor;
+    use crate::executor::{ sleep, sleep_until, spawn, Executor };
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{ Context, Poll };
+    use core::time::Duration;
+    use std::sync::{ Arc, Mutex };
+    use std::time::Instant;
+
+    //--------------------------------------------------------------------------
+    //  test_sleep
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_sleep()
+    {
+        let executor = Executor::new("test", 4).unwrap();
+        let num_done = Arc::new(Mutex::new(0));
+        let start = Instant::now();
+        for n in 0..1000
+        {
+            let num_done = num_done.clone();
+            executor.spawn(async move
+            {
+                let duration = Duration::from_millis(10 + n % 50);
+                sleep(duration).await;
+                assert!(start.elapsed() >= duration);
+                *num_done.lock().unwrap() += 1;
+            });
+        }
+        executor.run();
+        assert_eq!(1000, *num_done.lock().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_sleep_order
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_sleep_order()
+    {
+        let executor = Executor::builder("test").flavor(Flavor::CurrentThread).build().unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+        for n in (0..100).rev()
+        {
+            let order = order.clone();
+            executor.spawn(async move
+            {
+                sleep_until(start + Duration::from_millis(20 + n / 10 * 5)).await;
+                order.lock().unwrap().push(n / 10);
+            });
+        }
+        executor.run();
+
+        //  期限の早いものから順に完了する
+        let order = order.lock().unwrap();
+        assert_eq!(100, order.len());
+        assert!(order.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_sleep_cancel
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_sleep_cancel()
+    {
+        let executor = Executor::builder("test").flavor(Flavor::CurrentThread).build().unwrap();
+        executor.block_on(async
+        {
+            //  期限の早いSleepをドロップしても、他のSleepは影響を受けない
+            let mut early = sleep(Duration::from_millis(10));
+            let late = sleep_until(Instant::now() + Duration::from_millis(30));
+            let ready = PollOnce(&mut early).await;
+            assert!(!ready);
+            drop(early);
+            late.await;
+
+            //  中断されたタスクのSleepも取り消される
+            let handle = spawn(sleep(Duration::from_secs(3600)));
+            sleep(Duration::from_millis(10)).await;
+            handle.abort();
+            assert!(handle.await.unwrap_err().is_cancelled());
+
+            //  期限を過ぎたSleepはすぐに完了する
+            sleep(Duration::ZERO).await;
+        });
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_sleep_max
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_sleep_max()
+    {
+        let executor = Executor::builder("test").flavor(Flavor::CurrentThread).build().unwrap();
+        executor.block_on(async
+        {
+            //  `Instant` で表せない長さでもpanicせずに待ち続ける
+            let mut forever = sleep(Duration::MAX);
+            assert!(forever.deadline() > Instant::now() + Duration::from_secs(86400 * 365));
+            let ready = PollOnce(&mut forever).await;
+            assert!(!ready);
+        });
+    }
+
+    //--------------------------------------------------------------------------
+    //  1回だけポーリングして、完了したかどうかを返すFuture
+    //--------------------------------------------------------------------------
+    struct PollOnce<'a, F>(&'a mut F);
+
+    impl<F: Future + Unpin> Future for PollOnce<'_, F>
+    {
+        type Output = bool;
+
+        fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<bool>
+        {
+            Poll::Ready(Pin::new(&mut *self.0).poll(cx).is_ready())
+        }
+    }
+}
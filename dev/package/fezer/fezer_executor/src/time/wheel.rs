@@ -0,0 +1,426 @@
+/*
+
+    階層型タイマーホイール
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    期限を1ミリ秒単位のティックで管理する。ホイールは64スロットのレベルを6段
+    重ねたもので、レベル `n` の1スロットは `64^n` ティックを表す。
+
+    - 登録したエントリは、現在のティックとの差に応じたレベルのスロットに追加さ
+      れる。追加と削除はどちらもO(1)
+    - 時刻を進めると、期限の来たスロットのエントリを取り出す。上位のレベルのエ
+      ントリは期限までの残りに応じて下位のレベルに移される
+    - 各レベルは使用中のスロットをビットマスクで持つので、次の期限はレベルごと
+      に1回のビット演算で求まる
+
+    `64^6` ティック（約795日）より先の期限は最上位のレベルを一周するごとに再登
+    録される。
+
+*/
+
+use core::task::Waker;
+
+//  1レベルのスロット数のビット数
+const SLOT_BITS: u32 = 6;
+
+//  1レベルのスロット数
+const NUM_SLOTS: usize = 1 << SLOT_BITS;
+
+//  レベルの数
+const NUM_LEVELS: usize = 6;
+
+//  ホイール全体で表せるティック数
+const MAX_DURATION: u64 = 1 << (SLOT_BITS as usize * NUM_LEVELS);
+
+//------------------------------------------------------------------------------
+//  タイマーのエントリ
+//------------------------------------------------------------------------------
+struct Entry
+{
+    //  期限のティック
+    when: u64,
+
+    //  期限が来たときに起床するWaker
+    waker: Option<Waker>,
+
+    //  期限が来たかどうか
+    fired: bool,
+
+    //  登録されているレベル、スロット、スロット内の位置
+    position: Option<(usize, usize, usize)>,
+}
+
+//------------------------------------------------------------------------------
+//  ホイールの1レベル
+//------------------------------------------------------------------------------
+struct Level
+{
+    //  レベルの番号
+    level: usize,
+
+    //  使用中のスロットのビットマスク
+    occupied: u64,
+
+    //  各スロットに登録されたエントリのキー
+    slots: Vec<Vec<usize>>,
+}
+
+impl Level
+{
+    //--------------------------------------------------------------------------
+    //  新しいレベルを生成
+    //--------------------------------------------------------------------------
+    fn new( level: usize ) -> Self
+    {
+        Self
+        {
+            level,
+            occupied: 0,
+            slots: (0..NUM_SLOTS).map(|_| Vec::new()).collect(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  1スロットのティック数
+    //--------------------------------------------------------------------------
+    fn slot_range( &self ) -> u64
+    {
+        1 << (SLOT_BITS as usize * self.level)
+    }
+
+    //--------------------------------------------------------------------------
+    //  期限に対応するスロット
+    //--------------------------------------------------------------------------
+    fn slot_for( &self, when: u64 ) -> usize
+    {
+        ((when >> (SLOT_BITS as usize * self.level)) as usize) % NUM_SLOTS
+    }
+
+    //--------------------------------------------------------------------------
+    //  `now` の後で最初に期限が来る使用中のスロットと、その期限
+    //--------------------------------------------------------------------------
+    fn next_occupied( &self, now: u64 ) -> Option<(usize, u64)>
+    {
+        if self.occupied == 0
+        {
+            return None;
+        }
+
+        let slot_range = self.slot_range();
+        let level_range = slot_range * NUM_SLOTS as u64;
+        let now_slot = self.slot_for(now);
+        let offset = self.occupied.rotate_right(now_slot as u32).trailing_zeros() as usize;
+        let slot = (now_slot + offset) % NUM_SLOTS;
+
+        //  現在のスロットより前のスロットは次の周回
+        let level_start = now & !(level_range - 1);
+        let mut deadline = level_start + slot as u64 * slot_range;
+        if deadline <= now && self.level > 0
+        {
+            deadline += level_range;
+        }
+        Some((slot, deadline))
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Wheel
+//------------------------------------------------------------------------------
+pub(crate) struct Wheel
+{
+    //  現在のティック
+    elapsed: u64,
+
+    //  下位から順に並んだレベル
+    levels: Vec<Level>,
+
+    //  エントリの格納場所
+    //  キーはこの配列の添字
+    entries: Vec<Option<Entry>>,
+
+    //  空いている `entries` の添字
+    free: Vec<usize>,
+}
+
+impl Wheel
+{
+    //--------------------------------------------------------------------------
+    //  新しいホイールを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            elapsed: 0,
+            levels: (0..NUM_LEVELS).map(Level::new).collect(),
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のティック
+    //--------------------------------------------------------------------------
+    pub(crate) fn elapsed( &self ) -> u64
+    {
+        self.elapsed
+    }
+
+    //--------------------------------------------------------------------------
+    //  エントリを登録してキーを返す
+    //  期限が既に来ている場合は、期限切れのエントリとして登録する
+    //--------------------------------------------------------------------------
+    pub(crate) fn insert( &mut self, when: u64, waker: Waker ) -> usize
+    {
+        let entry = Entry { when, waker: Some(waker), fired: false, position: None };
+        let key = match self.free.pop()
+        {
+            Some(key) =>
+            {
+                self.entries[key] = Some(entry);
+                key
+            },
+            None =>
+            {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            },
+        };
+
+        if when <= self.elapsed
+        {
+            self.entry_mut(key).fired = true;
+        }
+        else
+        {
+            self.link(key);
+        }
+        key
+    }
+
+    //--------------------------------------------------------------------------
+    //  エントリの期限が来たかどうか
+    //  期限が来ていなければ、起床するWakerを更新する
+    //--------------------------------------------------------------------------
+    pub(crate) fn poll( &mut self, key: usize, waker: &Waker ) -> bool
+    {
+        let entry = self.entry_mut(key);
+        if entry.fired
+        {
+            return true;
+        }
+        match &entry.waker
+        {
+            Some(current) if current.will_wake(waker) => {},
+            _ => entry.waker = Some(waker.clone()),
+        }
+        false
+    }
+
+    //--------------------------------------------------------------------------
+    //  エントリを削除
+    //--------------------------------------------------------------------------
+    pub(crate) fn remove( &mut self, key: usize )
+    {
+        self.unlink(key);
+        self.entries[key] = None;
+        self.free.push(key);
+    }
+
+    //--------------------------------------------------------------------------
+    //  次に期限が来るスロットのティック
+    //--------------------------------------------------------------------------
+    pub(crate) fn next_deadline( &self ) -> Option<u64>
+    {
+        self.next_expiration().map(|(_, _, deadline)| deadline)
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のティックを `now` まで進め、期限が来たエントリのWakerを返す
+    //--------------------------------------------------------------------------
+    pub(crate) fn advance( &mut self, now: u64 ) -> Vec<Waker>
+    {
+        let mut wakers = Vec::new();
+        while let Some((level, slot, deadline)) = self.next_expiration()
+        {
+            if deadline > now
+            {
+                break;
+            }
+            self.elapsed = self.elapsed.max(deadline);
+
+            //  期限が来ていないエントリは下位のレベルに移す
+            let keys = std::mem::take(&mut self.levels[level].slots[slot]);
+            self.levels[level].occupied &= !(1 << slot);
+            for key in keys
+            {
+                let elapsed = self.elapsed;
+                let entry = self.entry_mut(key);
+                entry.position = None;
+                if entry.when <= elapsed
+                {
+                    entry.fired = true;
+                    wakers.extend(entry.waker.take());
+                }
+                else
+                {
+                    self.link(key);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+        wakers
+    }
+
+    //--------------------------------------------------------------------------
+    //  次に期限が来るスロット
+    //--------------------------------------------------------------------------
+    fn next_expiration( &self ) -> Option<(usize, usize, u64)>
+    {
+        self.levels
+            .iter()
+            .find_map(|level| level.next_occupied(self.elapsed).map(|(slot, deadline)| (level.level, slot, deadline)))
+    }
+
+    //--------------------------------------------------------------------------
+    //  エントリを期限に対応するスロットに追加
+    //--------------------------------------------------------------------------
+    fn link( &mut self, key: usize )
+    {
+        let when = self.entry_mut(key).when;
+
+        //  現在のティックと期限で異なる最上位のビットからレベルを決める
+        let mut masked = (self.elapsed ^ when) | (NUM_SLOTS as u64 - 1);
+        if masked >= MAX_DURATION
+        {
+            masked = MAX_DURATION - 1;
+        }
+        let significant = 63 - masked.leading_zeros() as usize;
+        let level = significant / SLOT_BITS as usize;
+
+        let slot = self.levels[level].slot_for(when);
+        let slots = &mut self.levels[level].slots[slot];
+        slots.push(key);
+        let index = slots.len() - 1;
+        self.levels[level].occupied |= 1 << slot;
+        self.entry_mut(key).position = Some((level, slot, index));
+    }
+
+    //--------------------------------------------------------------------------
+    //  エントリをスロットから取り除く
+    //--------------------------------------------------------------------------
+    fn unlink( &mut self, key: usize )
+    {
+        let Some((level, slot, index)) = self.entry_mut(key).position.take() else { return };
+        let slots = &mut self.levels[level].slots[slot];
+        slots.swap_remove(index);
+
+        //  末尾から移動したエントリの位置を更新
+        match slots.get(index).copied()
+        {
+            Some(moved) => self.entry_mut(moved).position = Some((level, slot, index)),
+            None if slots.is_empty() => self.levels[level].occupied &= !(1 << slot),
+            None => {},
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キーに対応するエントリ
+    //--------------------------------------------------------------------------
+    fn entry_mut( &mut self, key: usize ) -> &mut Entry
+    {
+        self.entries[key].as_mut().expect("invalid timer key")
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::Wheel;
+    use core::task::Waker;
+    use std::sync::{ Arc, Mutex };
+    use std::task::Wake;
+
+    //--------------------------------------------------------------------------
+    //  起床された順に番号を記録するWaker
+    //--------------------------------------------------------------------------
+    struct RecordWaker
+    {
+        id: u64,
+        woken: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Wake for RecordWaker
+    {
+        fn wake( self: Arc<Self> )
+        {
+            self.woken.lock().unwrap().push(self.id);
+        }
+    }
+
+    fn waker( id: u64, woken: &Arc<Mutex<Vec<u64>>> ) -> Waker
+    {
+        Waker::from(Arc::new(RecordWaker { id, woken: woken.clone() }))
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_wheel_advance
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_wheel_advance()
+    {
+        let woken = Arc::new(Mutex::new(Vec::new()));
+        let mut wheel = Wheel::new();
+        let deadlines = [1, 63, 64, 65, 4095, 4096, 300_000, 20_000_000];
+        for when in deadlines.iter().rev()
+        {
+            wheel.insert(*when, waker(*when, &woken));
+        }
+
+        //  上位のレベルのエントリも、期限のティックちょうどに起床される
+        while let Some(deadline) = wheel.next_deadline()
+        {
+            assert!(wheel.advance(deadline - 1).is_empty());
+            let num_woken = woken.lock().unwrap().len();
+            wheel.advance(deadline).into_iter().for_each(Waker::wake);
+            assert!(woken.lock().unwrap()[num_woken..].iter().all(|&when| when == deadline));
+        }
+        assert_eq!(deadlines.to_vec(), *woken.lock().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_wheel_remove
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_wheel_remove()
+    {
+        let woken = Arc::new(Mutex::new(Vec::new()));
+        let mut wheel = Wheel::new();
+        let keys: Vec<_> = (0..10).map(|id| wheel.insert(100, waker(id, &woken))).collect();
+
+        //  削除したエントリは起床されず、キーは再利用される
+        for key in keys.iter().step_by(2)
+        {
+            wheel.remove(*key);
+        }
+        let key = wheel.insert(50, waker(10, &woken));
+        assert!(keys.contains(&key));
+        wheel.remove(key);
+
+        wheel.advance(1000).into_iter().for_each(Waker::wake);
+        let mut woken = woken.lock().unwrap().clone();
+        woken.sort();
+        assert_eq!(vec![1, 3, 5, 7, 9], woken);
+
+        //  期限が過ぎてから登録したエントリはすぐに期限切れになる
+        let waker = waker(11, &Arc::new(Mutex::new(Vec::new())));
+        let key = wheel.insert(500, waker.clone());
+        assert!(wheel.poll(key, &waker));
+    }
+}
@@ -0,0 +1,533 @@
+/*
+
+    エントリポイントの属性マクロ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `async fn` の本体を `async move` ブロックに包み、Executorを生成して
+    `Executor::block_on()` で実行する同期の関数に書き換える。
+
+    ```rust
+    fn main() -> Result<(), E>
+    {
+        let body: Pin<&mut dyn Future<Output = Result<(), E>>> = pin!(async move
+        {
+            /* 元の本体 */
+        });
+        ::fezer::Executor::builder("main")
+            .flavor(::fezer::Flavor::MultiThread)
+            .num_threads(N)
+            .build()
+            .expect("failed to build fezer runtime")
+            .block_on(body)
+    }
+    ```
+
+    `#[fezer::test]` は `#[test]` の関数に書き換え、テストごとに新しいExecutor
+    を生成する `test_runner::run_test()` に本体を渡す。戻り値の型は
+    `run_test()` で検査するので、生成する関数は戻り値を持たない。
+
+    ```rust
+    #[::core::prelude::v1::test]
+    fn name()
+    {
+        ::fezer::test_runner::run_test(
+            concat!(module_path!(), "::", "name"),
+            ::fezer::Flavor::MultiThread,
+            N,
+            Some(Duration::from_millis(timeout_ms)),
+            || -> Pin<Box<dyn Future<Output = T>>> { Box::pin(async move { /* 元の本体 */ }) },
+        )
+    }
+    ```
+
+    元の本体のトークンはそのまま残すので、コンパイルエラーは元のコードの位置で
+    報告される。
+
+*/
+
+use proc_macro::{ Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree };
+
+//------------------------------------------------------------------------------
+//  マクロのエラー
+//------------------------------------------------------------------------------
+struct Error
+{
+    //  エラーを報告する位置
+    span: Span,
+
+    //  エラーメッセージ
+    message: String,
+}
+
+impl Error
+{
+    //--------------------------------------------------------------------------
+    //  新しいエラーを生成
+    //--------------------------------------------------------------------------
+    fn new( span: Span, message: impl Into<String> ) -> Self
+    {
+        Self { span, message: message.into() }
+    }
+
+    //--------------------------------------------------------------------------
+    //  `compile_error!` の呼び出しに変換
+    //--------------------------------------------------------------------------
+    fn to_compile_error( &self ) -> TokenStream
+    {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let tokens: Vec<TokenTree> = vec!
+        [
+            Punct::new(':', Spacing::Joint).into(),
+            Punct::new(':', Spacing::Alone).into(),
+            Ident::new("core", self.span).into(),
+            Punct::new(':', Spacing::Joint).into(),
+            Punct::new(':', Spacing::Alone).into(),
+            Ident::new("compile_error", self.span).into(),
+            Punct::new('!', Spacing::Alone).into(),
+            Group::new(Delimiter::Parenthesis, TokenTree::from(message).into()).into(),
+            Punct::new(';', Spacing::Alone).into(),
+        ];
+        tokens.into_iter()
+            .map(|mut token| { token.set_span(self.span); token })
+            .collect()
+    }
+}
+
+//------------------------------------------------------------------------------
+//  属性マクロの種類
+//------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Entry
+{
+    Main,
+    Test,
+}
+
+//------------------------------------------------------------------------------
+//  タスクを実行するスレッドの種類
+//------------------------------------------------------------------------------
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flavor
+{
+    CurrentThread,
+    MultiThread,
+}
+
+//------------------------------------------------------------------------------
+//  属性で指定された設定
+//------------------------------------------------------------------------------
+struct Config
+{
+    //  タスクを実行するスレッドの種類
+    flavor: Flavor,
+
+    //  タスクを実行するスレッド数
+    //  `None` の場合は利用可能なCPU数
+    worker_threads: Option<usize>,
+
+    //  テストのタイムアウト（ミリ秒）
+    //  `#[fezer::test]` だけで指定できる
+    timeout_ms: Option<u64>,
+}
+
+impl Default for Config
+{
+    //--------------------------------------------------------------------------
+    //  default
+    //--------------------------------------------------------------------------
+    fn default() -> Self
+    {
+        Self { flavor: Flavor::MultiThread, worker_threads: None, timeout_ms: None }
+    }
+}
+
+impl Config
+{
+    //--------------------------------------------------------------------------
+    //  属性の `key = value, ...` を解析
+    //--------------------------------------------------------------------------
+    fn parse( entry: Entry, attr: TokenStream ) -> Result<Self, Error>
+    {
+        let mut flavor = None;
+        let mut worker_threads = None;
+        let mut timeout_ms = None;
+        for (key, value) in parse_args(attr)?
+        {
+            match key.to_string().as_str()
+            {
+                "flavor" =>
+                {
+                    flavor = Some(match parse_str(&value)?.as_str()
+                    {
+                        "current_thread" => Flavor::CurrentThread,
+                        "multi_thread" => Flavor::MultiThread,
+                        _ => return Err(Error::new
+                        (
+                            value.span(),
+                            "flavor must be \"current_thread\" or \"multi_thread\"",
+                        )),
+                    });
+                },
+                "worker_threads" =>
+                {
+                    let n = parse_usize(&value)?;
+                    if n == 0
+                    {
+                        return Err(Error::new(value.span(), "worker_threads must be greater than 0"));
+                    }
+                    worker_threads = Some((n, value.span()));
+                },
+                "timeout_ms" if entry == Entry::Test =>
+                {
+                    let ms = parse_usize(&value)?;
+                    if ms == 0
+                    {
+                        return Err(Error::new(value.span(), "timeout_ms must be greater than 0"));
+                    }
+                    timeout_ms = Some(ms as u64);
+                },
+                name => return Err(Error::new(key.span(), format!("unknown option `{}`", name))),
+            }
+        }
+
+        let flavor = flavor.unwrap_or(Flavor::MultiThread);
+        if let (Flavor::CurrentThread, Some((_, span))) = (flavor, worker_threads)
+        {
+            return Err(Error::new(span, "worker_threads cannot be set with the current_thread flavor"));
+        }
+        Ok(Self { flavor, worker_threads: worker_threads.map(|(n, _)| n), timeout_ms })
+    }
+}
+
+//------------------------------------------------------------------------------
+//  属性の引数を `(key, value)` のリストに分解
+//------------------------------------------------------------------------------
+fn parse_args( attr: TokenStream ) -> Result<Vec<(Ident, Literal)>, Error>
+{
+    let mut args = Vec::new();
+    let mut tokens = attr.into_iter();
+    while let Some(token) = tokens.next()
+    {
+        let key = match token
+        {
+            TokenTree::Ident(key) => key,
+            token => return Err(Error::new(token.span(), "expected `key = value`")),
+        };
+        match tokens.next()
+        {
+            Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {},
+            _ => return Err(Error::new(key.span(), "expected `=` after the option name")),
+        }
+        let value = match tokens.next()
+        {
+            Some(TokenTree::Literal(value)) => value,
+            _ => return Err(Error::new(key.span(), "expected a literal value")),
+        };
+        match tokens.next()
+        {
+            None => {},
+            Some(TokenTree::Punct(punct)) if punct.as_char() == ',' => {},
+            Some(token) => return Err(Error::new(token.span(), "expected `,`")),
+        }
+        args.push((key, value));
+    }
+    Ok(args)
+}
+
+//------------------------------------------------------------------------------
+//  文字列リテラルの値を取得
+//------------------------------------------------------------------------------
+fn parse_str( literal: &Literal ) -> Result<String, Error>
+{
+    let s = literal.to_string();
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+    {
+        Some(s) => Ok(s.to_string()),
+        None => Err(Error::new(literal.span(), "expected a string literal")),
+    }
+}
+
+//------------------------------------------------------------------------------
+//  整数リテラルの値を取得
+//------------------------------------------------------------------------------
+fn parse_usize( literal: &Literal ) -> Result<usize, Error>
+{
+    let s = literal.to_string();
+    let digits = s.strip_suffix("usize").unwrap_or(&s).replace('_', "");
+    digits.parse().map_err(|_| Error::new(literal.span(), "expected an integer literal"))
+}
+
+//------------------------------------------------------------------------------
+//  関数を構成するトークン
+//------------------------------------------------------------------------------
+struct ItemFn
+{
+    //  `async` を除いた、本体より前のトークン
+    signature: Vec<TokenTree>,
+
+    //  `signature` のうち、戻り値の型より前のトークンの数
+    inputs_end: usize,
+
+    //  関数名
+    name: Ident,
+
+    //  戻り値の型
+    //  省略されている場合は空
+    output: Vec<TokenTree>,
+
+    //  関数の本体
+    body: Group,
+}
+
+impl ItemFn
+{
+    //--------------------------------------------------------------------------
+    //  `async fn` を分解
+    //--------------------------------------------------------------------------
+    fn parse( item: TokenStream ) -> Result<Self, Error>
+    {
+        let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+        let body = match tokens.pop()
+        {
+            Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+            token =>
+            {
+                let span = token.map_or(Span::call_site(), |token| token.span());
+                return Err(Error::new(span, "expected a function"));
+            },
+        };
+
+        //  `fn` より前にある `async` を取り除く
+        let fn_pos = tokens.iter()
+            .position(|token| is_ident(token, "fn"))
+            .ok_or_else(|| Error::new(body.span(), "expected a function"))?;
+        let async_pos = tokens[..fn_pos].iter()
+            .position(|token| is_ident(token, "async"))
+            .ok_or_else(|| Error::new(tokens[fn_pos].span(), "the `async` keyword is missing from the function declaration"))?;
+        tokens.remove(async_pos);
+        let name = match tokens.get(fn_pos)
+        {
+            Some(TokenTree::Ident(name)) => name.clone(),
+            _ => return Err(Error::new(body.span(), "expected a function name")),
+        };
+
+        //  `->` から `where` か本体までが戻り値の型
+        let mut output = Vec::new();
+        let mut inputs_end = tokens.len();
+        let arrow = tokens.windows(2).position(|pair| match pair
+        {
+            [TokenTree::Punct(a), TokenTree::Punct(b)] => a.as_char() == '-' && b.as_char() == '>',
+            _ => false,
+        });
+        if let Some(arrow) = arrow
+        {
+            inputs_end = arrow;
+            output = tokens[arrow + 2..]
+                .iter()
+                .take_while(|token| !is_ident(token, "where"))
+                .cloned()
+                .collect();
+        }
+
+        Ok(Self { signature: tokens, inputs_end, name, body, output })
+    }
+}
+
+//------------------------------------------------------------------------------
+//  トークンが指定された識別子かどうか
+//------------------------------------------------------------------------------
+fn is_ident( token: &TokenTree, name: &str ) -> bool
+{
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+//------------------------------------------------------------------------------
+//  文字列をトークン列に変換
+//------------------------------------------------------------------------------
+fn tokens( s: &str ) -> TokenStream
+{
+    s.parse().expect("invalid tokens")
+}
+
+//------------------------------------------------------------------------------
+//  本体の `async move` ブロック
+//------------------------------------------------------------------------------
+fn async_body( item: &ItemFn ) -> TokenStream
+{
+    let mut future = tokens("async move");
+    future.extend([TokenTree::Group(item.body.clone())]);
+    future
+}
+
+//------------------------------------------------------------------------------
+//  本体のFutureの `Output` の型
+//------------------------------------------------------------------------------
+fn output_type( item: &ItemFn ) -> TokenStream
+{
+    if item.output.is_empty()
+    {
+        tokens("()")
+    }
+    else
+    {
+        item.output.iter().cloned().collect()
+    }
+}
+
+//------------------------------------------------------------------------------
+//  `Flavor` の列挙子のパス
+//------------------------------------------------------------------------------
+fn flavor_path( config: &Config ) -> &'static str
+{
+    match config.flavor
+    {
+        Flavor::CurrentThread => "::fezer::Flavor::CurrentThread",
+        Flavor::MultiThread => "::fezer::Flavor::MultiThread",
+    }
+}
+
+//------------------------------------------------------------------------------
+//  タスクを実行するスレッド数の式
+//------------------------------------------------------------------------------
+fn num_threads( config: &Config ) -> String
+{
+    match config.worker_threads
+    {
+        Some(n) => n.to_string(),
+        None => "::std::thread::available_parallelism().map_or(1, ::core::num::NonZeroUsize::get)".to_string(),
+    }
+}
+
+//------------------------------------------------------------------------------
+//  新しい本体で関数を組み立てる
+//------------------------------------------------------------------------------
+fn with_body( signature: &[TokenTree], body: TokenStream, span: Span ) -> TokenStream
+{
+    let mut body = Group::new(Delimiter::Brace, body);
+    body.set_span(span);
+
+    let mut result: TokenStream = signature.iter().cloned().collect();
+    result.extend([TokenTree::Group(body)]);
+    result
+}
+
+//------------------------------------------------------------------------------
+//  Executorを生成して本体を実行するmain関数に書き換える
+//------------------------------------------------------------------------------
+fn expand_main( config: &Config, item: ItemFn ) -> TokenStream
+{
+    //  let body: Pin<&mut dyn Future<Output = T>> = pin!(async move { ... });
+    let mut body = tokens("let body: ::core::pin::Pin<&mut dyn ::core::future::Future<Output =");
+    body.extend(output_type(&item));
+    body.extend(tokens("> > = ::core::pin::pin!"));
+    body.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, async_body(&item)))]);
+    body.extend(tokens(";"));
+
+    //  Executorを生成して、本体を完了まで実行
+    body.extend(tokens(&format!
+    (
+        "::fezer::Executor::builder(\"main\")\
+            .flavor({})\
+            .num_threads({})\
+            .build()\
+            .expect(\"failed to build fezer runtime\")\
+            .block_on(body)",
+        flavor_path(config),
+        num_threads(config),
+    )));
+
+    with_body(&item.signature, body, item.body.span())
+}
+
+//------------------------------------------------------------------------------
+//  本体をテスト用のExecutorで実行するテスト関数に書き換える
+//------------------------------------------------------------------------------
+fn expand_test( config: &Config, item: ItemFn ) -> TokenStream
+{
+    //  || -> Pin<Box<dyn Future<Output = T>>> { Box::pin(async move { ... }) }
+    let mut future = tokens("|| -> ::core::pin::Pin<::std::boxed::Box<dyn ::core::future::Future<Output =");
+    future.extend(output_type(&item));
+    future.extend(tokens("> > >"));
+    let mut boxed = tokens("::std::boxed::Box::pin");
+    boxed.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, async_body(&item)))]);
+    future.extend([TokenTree::Group(Group::new(Delimiter::Brace, boxed))]);
+
+    let timeout = match config.timeout_ms
+    {
+        Some(ms) => format!("::core::option::Option::Some(::core::time::Duration::from_millis({}))", ms),
+        None => "::core::option::Option::None".to_string(),
+    };
+    let mut args = tokens(&format!
+    (
+        "::core::concat!(::core::module_path!(), \"::\", {:?}), {}, {}, {},",
+        item.name.to_string(),
+        flavor_path(config),
+        num_threads(config),
+        timeout,
+    ));
+    args.extend(future);
+
+    let mut body = tokens("::fezer::test_runner::run_test");
+    body.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, args))]);
+
+    let mut result = tokens("#[::core::prelude::v1::test]");
+    result.extend(with_body(&item.signature[..item.inputs_end], body, item.body.span()));
+    result
+}
+
+//------------------------------------------------------------------------------
+//  属性マクロを展開
+//------------------------------------------------------------------------------
+fn expand( entry: Entry, attr: TokenStream, item: TokenStream ) -> TokenStream
+{
+    let expand_fn = match entry
+    {
+        Entry::Main => expand_main,
+        Entry::Test => expand_test,
+    };
+
+    let item_fn = match ItemFn::parse(item.clone())
+    {
+        Ok(item_fn) => item_fn,
+
+        //  元の関数も出力して、関数が見つからないという余計なエラーを防ぐ
+        Err(e) =>
+        {
+            let mut result = e.to_compile_error();
+            result.extend(item);
+            return result;
+        },
+    };
+    match Config::parse(entry, attr)
+    {
+        Ok(config) => expand_fn(&config, item_fn),
+
+        //  設定のエラーだけを報告するために、既定の設定で展開しておく
+        Err(e) =>
+        {
+            let mut result = e.to_compile_error();
+            result.extend(expand_fn(&Config::default(), item_fn));
+            result
+        },
+    }
+}
+
+//------------------------------------------------------------------------------
+//  #[fezer::main]
+//------------------------------------------------------------------------------
+pub(crate) fn main( attr: TokenStream, item: TokenStream ) -> TokenStream
+{
+    expand(Entry::Main, attr, item)
+}
+
+//------------------------------------------------------------------------------
+//  #[fezer::test]
+//------------------------------------------------------------------------------
+pub(crate) fn test( attr: TokenStream, item: TokenStream ) -> TokenStream
+{
+    expand(Entry::Test, attr, item)
+}
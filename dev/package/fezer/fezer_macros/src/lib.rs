@@ -0,0 +1,64 @@
+/*
+
+    fezerの手続きマクロ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `fezer` クレートから再エクスポートされる属性マクロを定義する。
+
+    - `#[fezer::main]` は `async fn main()` をExecutorの `block_on()` で実行す
+      る同期の `fn main()` に変換する
+    - `#[fezer::test]` は `async fn` のテストを、テストごとに新しく生成した
+      Executorで実行する
+
+    # 使用例
+
+    ```rust
+    #[fezer::main(flavor = "multi_thread", worker_threads = 4)]
+    async fn main() -> Result<(), Box<dyn std::error::Error>>
+    {
+        fezer::spawn(async { println!("child") }).await?;
+        Ok(())
+    }
+
+    #[fezer::test(flavor = "current_thread", timeout_ms = 1000)]
+    async fn test_spawn()
+    {
+        assert_eq!(2, fezer::spawn(async { 1 + 1 }).await.unwrap());
+    }
+    ```
+
+    # オプション
+
+    - `flavor = "current_thread" | "multi_thread"` はタスクを実行するスレッドの
+      種類。省略した場合は `"multi_thread"`
+    - `worker_threads = N` はタスクを実行するスレッド数。`"multi_thread"` のと
+      きだけ指定でき、省略した場合は利用可能なCPU数
+    - `timeout_ms = N` はテストのタイムアウト。`#[fezer::test]` だけで指定でき、
+      省略した場合はタイムアウトしない
+
+*/
+
+mod entry;
+
+use proc_macro::TokenStream;
+
+//------------------------------------------------------------------------------
+//  非同期のmain関数をExecutorで実行する
+//------------------------------------------------------------------------------
+#[proc_macro_attribute]
+pub fn main( attr: TokenStream, item: TokenStream ) -> TokenStream
+{
+    entry::main(attr, item)
+}
+
+//------------------------------------------------------------------------------
+//  非同期のテスト関数をテストごとに生成したExecutorで実行する
+//------------------------------------------------------------------------------
+#[proc_macro_attribute]
+pub fn test( attr: TokenStream, item: TokenStream ) -> TokenStream
+{
+    entry::test(attr, item)
+}
@@ -6,7 +6,8 @@
 
     # 概要
 
-    複数のスレッドから直接カウントアップしても安全なカウンタ。
+    複数のスレッドから直接カウントアップしても安全なカウンタ。スレッド名の通し
+    番号と、スレッドプールの統計情報のカウントに用いる。
 
     # 使用例
 
@@ -56,6 +57,22 @@ impl AtomicCounter
     {
         self.next_value.fetch_add(1, Ordering::AcqRel)
     }
+
+    //--------------------------------------------------------------------------
+    //  カウンタに値を加算
+    //--------------------------------------------------------------------------
+    pub fn add( &self, n: usize )
+    {
+        self.next_value.fetch_add(n, Ordering::AcqRel);
+    }
+
+    //--------------------------------------------------------------------------
+    //  カウンタの現在の値
+    //--------------------------------------------------------------------------
+    pub fn get( &self ) -> usize
+    {
+        self.next_value.load(Ordering::Acquire)
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -76,6 +93,8 @@ mod tests
         assert_eq!(0, counter.next());
         assert_eq!(1, counter.next());
         assert_eq!(2, counter.next());
+        counter.add(5);
+        assert_eq!(8, counter.get());
     }
 
     //--------------------------------------------------------------------------
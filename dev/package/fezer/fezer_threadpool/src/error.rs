@@ -4,6 +4,7 @@
 
 */
 
+use core::any::Any;
 use core::fmt::{ Debug, Display, Formatter };
 use std::error::Error;
 use std::io::ErrorKind;
@@ -29,6 +30,17 @@ pub enum StartThreadsError
     //  プールにスレッドが1つ以上あるが、スレッド起動時にエラーが発生した場合
     //  `std::thread::Builder::spawn()` のエラー
     Respawn(std::io::Error),
+
+    //  プールのスレッド数の上限か、共有する予算の上限に達した場合
+    //  到達した上限の値
+    LimitReached(usize),
+
+    //  スレッドの起動に続けて失敗してプールが劣化状態になり、スレッドがない場合
+    //  最後の `std::thread::Builder::spawn()` のエラー
+    PoolDegraded(std::io::Error),
+
+    //  パラメータが不正だった場合
+    Parameter(String),
 }
 
 impl Display for StartThreadsError
@@ -58,6 +70,15 @@ impl Display for StartThreadsError
                     e
                 )
             },
+            StartThreadsError::LimitReached(limit) =>
+            {
+                write!(f, "ThreadPool reached the thread limit: {}", limit)
+            },
+            StartThreadsError::PoolDegraded(e) =>
+            {
+                write!(f, "ThreadPool is degraded after repeated failures starting threads: {}", e)
+            },
+            StartThreadsError::Parameter(s) => write!(f, "{}", s),
         }
     }
 }
@@ -75,6 +96,9 @@ impl PartialEq for StartThreadsError
         {
             (StartThreadsError::NoThreads(a), StartThreadsError::NoThreads(b))
             | (StartThreadsError::Respawn(a), StartThreadsError::Respawn(b)) => err_eq(a, b),
+            (StartThreadsError::PoolDegraded(a), StartThreadsError::PoolDegraded(b)) => err_eq(a, b),
+            (StartThreadsError::LimitReached(a), StartThreadsError::LimitReached(b)) => a == b,
+            (StartThreadsError::Parameter(a), StartThreadsError::Parameter(b)) => a == b,
             _ => false,
         }
     }
@@ -94,6 +118,9 @@ pub enum NewThreadPoolError
     //  プール内のスレッド生成時（StartThreadsError）
     //  `std::thread::Builder::spawn()` のエラー
     Spawn(std::io::Error),
+
+    //  スレッド数の上限に達した場合（StartThreadsError）
+    LimitReached(usize),
 }
 
 impl Display for NewThreadPoolError
@@ -110,6 +137,10 @@ impl Display for NewThreadPoolError
             {
                 write!(f, "ThreadPool failed starting threads: {}", e)
             },
+            NewThreadPoolError::LimitReached(limit) =>
+            {
+                write!(f, "ThreadPool reached the thread limit: {}", limit)
+            },
         }
     }
 }
@@ -127,6 +158,7 @@ impl PartialEq for NewThreadPoolError
         {
             (NewThreadPoolError::Parameter(a), NewThreadPoolError::Parameter(b)) => a == b,
             (NewThreadPoolError::Spawn(a), NewThreadPoolError::Spawn(b)) => err_eq(a, b),
+            (NewThreadPoolError::LimitReached(a), NewThreadPoolError::LimitReached(b)) => a == b,
             _ => false,
         }
     }
@@ -141,10 +173,11 @@ impl From<StartThreadsError> for NewThreadPoolError
     {
         match err
         {
-            StartThreadsError::NoThreads(e) | StartThreadsError::Respawn(e) =>
-            {
-                NewThreadPoolError::Spawn(e)
-            }
+            StartThreadsError::NoThreads(e)
+            | StartThreadsError::Respawn(e)
+            | StartThreadsError::PoolDegraded(e) => NewThreadPoolError::Spawn(e),
+            StartThreadsError::LimitReached(limit) => NewThreadPoolError::LimitReached(limit),
+            StartThreadsError::Parameter(s) => NewThreadPoolError::Parameter(s),
         }
     }
 }
@@ -166,18 +199,22 @@ impl From<NewThreadPoolError> for std::io::Error
             {
                 std::io::Error::new(ErrorKind::Other, format!("failed to start threads: {}", s))
             },
+            NewThreadPoolError::LimitReached(limit) =>
+            {
+                std::io::Error::other(format!("reached the thread limit: {}", limit))
+            },
         }
     }
 }
 
 //------------------------------------------------------------------------------
 //  タスクスケジュール時のエラー
+//  ジョブをキューに追加できなかった場合は、ジョブのクロージャを返す
 //------------------------------------------------------------------------------
-#[derive(Debug)]
-pub enum TryScheduleError
+pub enum TryScheduleError<F>
 {
     //  タスクキューが一杯の場合
-    QueueFull,
+    QueueFull(F),
 
     //  プールにスレッドがない場合（StartThreadsError）
     NoThreads(std::io::Error),
@@ -185,9 +222,36 @@ pub enum TryScheduleError
     //  プールにスレッドが1つ以上あるが、スレッド起動時にエラーが発生した場合
     //  （StartThreadsError）
     Respawn(std::io::Error),
+
+    //  スレッド数の上限に達した場合（StartThreadsError）
+    LimitReached(usize),
+
+    //  ジョブのパニックによってプールが停止されていた場合
+    Aborted(F),
+
+    //  プールが劣化状態で、スレッドがない場合（StartThreadsError）
+    PoolDegraded(std::io::Error),
+
+    //  パラメータが不正だった場合（StartThreadsError）
+    Parameter(String),
+}
+
+impl<F> TryScheduleError<F>
+{
+    //--------------------------------------------------------------------------
+    //  キューに追加できなかったジョブのクロージャを取り出す
+    //--------------------------------------------------------------------------
+    pub fn into_inner( self ) -> Option<F>
+    {
+        match self
+        {
+            TryScheduleError::QueueFull(f) | TryScheduleError::Aborted(f) => Some(f),
+            _ => None,
+        }
+    }
 }
 
-impl Display for TryScheduleError
+impl<F> Debug for TryScheduleError<F>
 {
     //--------------------------------------------------------------------------
     //  fmt
@@ -196,7 +260,27 @@ impl Display for TryScheduleError
     {
         match self
         {
-            TryScheduleError::QueueFull => write!(f, "ThreadPool queue is full"),
+            TryScheduleError::QueueFull(_) => write!(f, "QueueFull(..)"),
+            TryScheduleError::NoThreads(e) => write!(f, "NoThreads({:?})", e),
+            TryScheduleError::Respawn(e) => write!(f, "Respawn({:?})", e),
+            TryScheduleError::LimitReached(limit) => write!(f, "LimitReached({:?})", limit),
+            TryScheduleError::Aborted(_) => write!(f, "Aborted(..)"),
+            TryScheduleError::PoolDegraded(e) => write!(f, "PoolDegraded({:?})", e),
+            TryScheduleError::Parameter(s) => write!(f, "Parameter({:?})", s),
+        }
+    }
+}
+
+impl<F> Display for TryScheduleError<F>
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            TryScheduleError::QueueFull(_) => write!(f, "ThreadPool queue is full"),
             TryScheduleError::NoThreads(e) =>
             {
                 write!
@@ -215,13 +299,23 @@ impl Display for TryScheduleError
                     e
                 )
             },
+            TryScheduleError::LimitReached(limit) =>
+            {
+                write!(f, "ThreadPool reached the thread limit: {}", limit)
+            },
+            TryScheduleError::Aborted(_) => write!(f, "ThreadPool was aborted by a panicked job"),
+            TryScheduleError::PoolDegraded(e) =>
+            {
+                write!(f, "ThreadPool is degraded after repeated failures starting threads: {}", e)
+            },
+            TryScheduleError::Parameter(s) => write!(f, "{}", s),
         }
     }
 }
 
-impl Error for TryScheduleError {}
+impl<F> Error for TryScheduleError<F> {}
 
-impl PartialEq for TryScheduleError
+impl<F> PartialEq for TryScheduleError<F>
 {
     //--------------------------------------------------------------------------
     //  eq
@@ -230,17 +324,21 @@ impl PartialEq for TryScheduleError
     {
         match (self, other)
         {
-            (TryScheduleError::QueueFull, TryScheduleError::QueueFull) => true,
+            (TryScheduleError::QueueFull(_), TryScheduleError::QueueFull(_))
+            | (TryScheduleError::Aborted(_), TryScheduleError::Aborted(_)) => true,
             (TryScheduleError::NoThreads(a), TryScheduleError::NoThreads(b))
             | (TryScheduleError::Respawn(a), TryScheduleError::Respawn(b)) => err_eq(a, b),
+            (TryScheduleError::PoolDegraded(a), TryScheduleError::PoolDegraded(b)) => err_eq(a, b),
+            (TryScheduleError::LimitReached(a), TryScheduleError::LimitReached(b)) => a == b,
+            (TryScheduleError::Parameter(a), TryScheduleError::Parameter(b)) => a == b,
             _ => false,
         }
     }
 }
 
-impl Eq for TryScheduleError {}
+impl<F> Eq for TryScheduleError<F> {}
 
-impl From<StartThreadsError> for TryScheduleError
+impl<F> From<StartThreadsError> for TryScheduleError<F>
 {
     //--------------------------------------------------------------------------
     //  from
@@ -251,20 +349,23 @@ impl From<StartThreadsError> for TryScheduleError
         {
             StartThreadsError::NoThreads(e) => TryScheduleError::NoThreads(e),
             StartThreadsError::Respawn(e) => TryScheduleError::Respawn(e),
+            StartThreadsError::LimitReached(limit) => TryScheduleError::LimitReached(limit),
+            StartThreadsError::PoolDegraded(e) => TryScheduleError::PoolDegraded(e),
+            StartThreadsError::Parameter(s) => TryScheduleError::Parameter(s),
         }
     }
 }
 
-impl From<TryScheduleError> for std::io::Error
+impl<F> From<TryScheduleError<F>> for std::io::Error
 {
     //--------------------------------------------------------------------------
     //  from
     //--------------------------------------------------------------------------
-    fn from( try_schedule_error: TryScheduleError ) -> Self
+    fn from( try_schedule_error: TryScheduleError<F> ) -> Self
     {
         match try_schedule_error
         {
-            TryScheduleError::QueueFull =>
+            TryScheduleError::QueueFull(_) =>
             {
                 std::io::Error::new(ErrorKind::WouldBlock, "TryScheduleError::QueueFull")
             },
@@ -292,6 +393,183 @@ impl From<TryScheduleError> for std::io::Error
                     )
                 )
             },
+            TryScheduleError::LimitReached(limit) =>
+            {
+                std::io::Error::new
+                (
+                    ErrorKind::WouldBlock,
+                    format!("ThreadPool reached the thread limit: {}", limit)
+                )
+            },
+            TryScheduleError::Aborted(_) =>
+            {
+                std::io::Error::new(ErrorKind::BrokenPipe, "TryScheduleError::Aborted")
+            },
+            TryScheduleError::PoolDegraded(e) =>
+            {
+                std::io::Error::new
+                (
+                    e.kind(),
+                    format!
+                    (
+                        "ThreadPool is degraded after repeated failures starting threads: {}",
+                        e
+                    )
+                )
+            },
+            TryScheduleError::Parameter(s) =>
+            {
+                std::io::Error::new(ErrorKind::InvalidInput, s)
+            },
         }
     }
 }
+
+//------------------------------------------------------------------------------
+//  ジョブの結果を待つときのエラー
+//------------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum JoinError
+{
+    //  ジョブがパニックになった場合
+    //  `std::panic::catch_unwind()` のペイロード
+    Panic(Box<dyn Any + Send + 'static>),
+
+    //  タイムアウトまでにジョブが完了しなかった場合
+    Timeout,
+
+    //  ジョブが実行されずに破棄された場合
+    Dropped,
+}
+
+impl JoinError
+{
+    //--------------------------------------------------------------------------
+    //  ジョブがパニックになったかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_panic( &self ) -> bool
+    {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    //--------------------------------------------------------------------------
+    //  パニックのペイロードを取得
+    //  ※ パニック以外のエラーで呼び出すとpanic
+    //--------------------------------------------------------------------------
+    pub fn into_panic( self ) -> Box<dyn Any + Send + 'static>
+    {
+        match self
+        {
+            JoinError::Panic(payload) => payload,
+            e => panic!("JoinError::into_panic() called on {:?}", e),
+        }
+    }
+}
+
+impl Display for JoinError
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            JoinError::Panic(payload) =>
+            {
+                match panic_message(payload.as_ref())
+                {
+                    Some(message) => write!(f, "ThreadPool job panicked: {}", message),
+                    None => write!(f, "ThreadPool job panicked"),
+                }
+            },
+            JoinError::Timeout => write!(f, "timed out waiting for ThreadPool job"),
+            JoinError::Dropped => write!(f, "ThreadPool job was dropped before completion"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+impl PartialEq for JoinError
+{
+    //--------------------------------------------------------------------------
+    //  eq
+    //  パニックのペイロードは比較できないので、パニック同士は等しくない
+    //--------------------------------------------------------------------------
+    fn eq( &self, other: &Self ) -> bool
+    {
+        matches!
+        (
+            (self, other),
+            (JoinError::Timeout, JoinError::Timeout) | (JoinError::Dropped, JoinError::Dropped)
+        )
+    }
+}
+
+//------------------------------------------------------------------------------
+//  パニックのペイロードからメッセージを取得
+//------------------------------------------------------------------------------
+pub(crate) fn panic_message( payload: &(dyn Any + Send) ) -> Option<&str>
+{
+    if let Some(s) = payload.downcast_ref::<&'static str>()
+    {
+        Some(s)
+    }
+    else
+    {
+        payload.downcast_ref::<String>().map(String::as_str)
+    }
+}
+
+//------------------------------------------------------------------------------
+//  スレッドプール停止時のエラー
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownError
+{
+    //  タイムアウトまでにスレッドが停止しなかった場合
+    Timeout
+    {
+        //  停止していないスレッドの数
+        num_running: usize,
+
+        //  実行されずに破棄されたジョブの数
+        num_discarded: usize,
+    },
+}
+
+impl Display for ShutdownError
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            ShutdownError::Timeout { num_running, .. } =>
+            {
+                write!
+                (
+                    f,
+                    "timed out waiting for ThreadPool workers to stop: {} still running",
+                    num_running
+                )
+            },
+        }
+    }
+}
+
+impl Error for ShutdownError {}
+
+impl From<ShutdownError> for std::io::Error
+{
+    //--------------------------------------------------------------------------
+    //  from
+    //--------------------------------------------------------------------------
+    fn from( shutdown_error: ShutdownError ) -> Self
+    {
+        std::io::Error::new(ErrorKind::TimedOut, shutdown_error.to_string())
+    }
+}
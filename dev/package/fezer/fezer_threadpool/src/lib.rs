@@ -8,33 +8,77 @@
 
     単体でもfezerの非同期ランタイムと合わせても利用可能なスレッドプール。
 
+    - ジョブのパニックはワーカーの内部で捕捉し、パニックハンドラに通知する
+    - 最初のパニックでプールを停止するように設定できる
     - パニックになったスレッドは自動的に再起動する
-    - スレッドの生成に失敗したときに再試行する
+    - スレッドの生成に失敗したときに、監視スレッドが指数バックオフで再試行する
+    - 生成の失敗が続くとプールは劣化状態になり、`schedule()` はスレッドの起動
+      を待ち続けずにジョブをキューに追加し、`try_schedule()` は失敗する
     - `drop()` 時はすべてのアイドルスレッドを停止して自身を削除する
-    - `drop()` 時はスレッドがすべて停止するまではメインスレッドをスリープする
+    - `join()` と `shutdown()` はスレッドがすべて停止するまで待つ
+    - `shutdown()` ではキューに残っているジョブを実行するか破棄するかを選べる
     - クロージャか `FnOnce` をスケジュールして、いずれかのスレッドで実行する
+    - ジョブはワークスティーリングのキューでスケジュールされる
+    - ジョブは優先度を指定してスケジュールでき、低い優先度のジョブも一定の間隔
+      で実行される
+    - `schedule_after()`、`schedule_at()`、`schedule_every()` で遅延ジョブと周
+      期ジョブをスケジュールし、ハンドルからキャンセルできる
+    - `par_map()`、`par_for_each()`、`par_chunks()` でコレクションを並列に処理
+      できる
+    - Linuxではワーカースレッドを `CpuAffinity` でCPUコアにピン留めできる
+    - `schedule_async()` はキューが一杯のときにスレッドを停止せずに空きを待つ
+    - `schedule_batch()` と `try_schedule_batch()` で多数のジョブをまとめてキ
+      ューに追加できる
+    - `schedule_cancellable()` でスケジュールしたジョブは `CancelHandle` でキャ
+      ンセルでき、実行中のジョブは `CancellationToken` で中断を確認できる
+    - `stats()` でキューのジョブ数、実行中のワーカー数、完了したジョブ数など
+      の統計情報を取得できる
+    - `set_size()` で実行中にスレッド数を変更できる
+    - `new_elastic()` で生成したプールは、ジョブの量に合わせて最小スレッド数と
+      最大スレッド数の間で伸縮する
+    - `ThreadPoolBuilder` でキューの上限、スタックサイズ、スレッド名、スレッド
+      の開始時と停止時のフックを設定できる
+    - プールごとのスレッド数の上限と、複数のプールで共有する `ThreadBudget`
+      でスレッド数を制限できる
 
     # 使用例
 
     ```rust
     let pool = fezer_threadpool::ThreadPool::new("worker", 5).unwrap();
-    let receiver =
-    {
-        let (sender, receiver) = std::sync::mpsc::channel();
+    let handles: Vec<_> = data_source
+        .into_iter()
+        .map(|data| pool.spawn(move || process_data(data)))
+        .collect();
+    let results: Vec<ProcessResult> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+    ```
+
+    `spawn()` が返す `JoinHandle` は `.await` で非同期に待つこともできる。ジョブ
+    がパニックになった場合は `JoinError::Panic` が返る。
 
-        for data in data_source
+    呼び出し元のスタックを借用するジョブは `scope()` でスケジュールする。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 5).unwrap();
+    let mut results = vec![ProcessResult::default(); data_source.len()];
+    pool.scope(|s|
+    {
+        for (data, result) in data_source.iter().zip(results.iter_mut())
         {
-            let sender_clone = sender.clone();
-            pool.schedule(move || process_data(data, sender_clone));
+            s.spawn(move || *result = process_data(data));
         }
-        receiver
-    };
-    let results: Vec<ProcessResult> = receiver.iter().collect();
+    });
     ```
 
-    # TODO
+    コレクションを並列に処理して順序どおりに結果を集める場合は `par_map()` が
+    使える。
 
-    - 性能向上のため、ジョブのスケジューリングにワークスティーリングのキューを採用
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 5).unwrap();
+    let results: Vec<ProcessResult> = pool.par_map(data_source, process_data);
+    ```
 
 */
 
@@ -44,4 +88,22 @@ mod atomic_counter;
 mod threadpool;
 pub mod error;
 
-pub use threadpool::ThreadPool;
+pub use threadpool::
+{
+    CancelHandle,
+    CancellationToken,
+    CpuAffinity,
+    JoinHandle,
+    Periodic,
+    Priority,
+    ScheduleFut,
+    Scope,
+    ShutdownMode,
+    ShutdownReport,
+    ThreadBudget,
+    ThreadPool,
+    ThreadPoolBuilder,
+    ThreadPoolStats,
+    TimerHandle,
+    WorkerStats,
+};
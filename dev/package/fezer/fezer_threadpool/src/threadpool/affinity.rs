@@ -0,0 +1,233 @@
+/*
+
+    ワーカースレッドのCPUアフィニティ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    ワーカースレッドを特定のCPUコアにピン留めする。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::builder("worker")
+        .size(4)
+        .cpu_affinity(CpuAffinity::RoundRobin(vec![2, 3]))
+        .build()
+        .unwrap();
+    ```
+
+    - `CpuAffinity::RoundRobin` は許可されたCPUの集合をスロットの順に繰り返し割
+      り当てる
+    - `CpuAffinity::Explicit` はスロットごとにCPUを指定する。リストより後ろのス
+      ロットのワーカーはピン留めしない
+
+    CPUはワーカーのスロットのインデックスから決まるので、パニックで停止したワー
+    カーのスロットを引き継いだワーカーは同じCPUにピン留めされる。
+
+    ピン留めはLinuxの `sched_setaffinity` で行う。Linux以外ではピン留めせずに動
+    作する。ワーカーの起動時にピン留めに失敗した場合も、ピン留めせずに動作を続
+    ける。失敗したワーカーの数は `ThreadPoolStats::num_pin_failures` に記録され
+    る。
+
+*/
+
+use std::io;
+
+//  `cpu_set_t` で扱えるCPUの数
+pub(crate) const CPU_SETSIZE: usize = 1024;
+
+//  `cpu_set_t` のワード数
+const CPU_SET_WORDS: usize = CPU_SETSIZE / 64;
+
+//------------------------------------------------------------------------------
+//  ワーカーへのCPUの割り当て方
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuAffinity
+{
+    //  許可されたCPUの集合をスロットの順に繰り返し割り当てる
+    RoundRobin(Vec<usize>),
+
+    //  スロットごとにCPUを指定する
+    Explicit(Vec<usize>),
+}
+
+impl CpuAffinity
+{
+    //--------------------------------------------------------------------------
+    //  指定されたCPUのリスト
+    //--------------------------------------------------------------------------
+    pub(crate) fn cpus( &self ) -> &[usize]
+    {
+        match self
+        {
+            CpuAffinity::RoundRobin(cpus) | CpuAffinity::Explicit(cpus) => cpus,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  スロットのワーカーをピン留めするCPU
+    //--------------------------------------------------------------------------
+    pub(crate) fn cpu_for_slot( &self, index: usize ) -> Option<usize>
+    {
+        match self
+        {
+            CpuAffinity::RoundRobin(cpus) if !cpus.is_empty() => Some(cpus[index % cpus.len()]),
+            CpuAffinity::RoundRobin(_) => None,
+            CpuAffinity::Explicit(cpus) => cpus.get(index).copied(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+extern "C"
+{
+    fn sched_setaffinity( pid: i32, cpusetsize: usize, mask: *const u64 ) -> i32;
+    fn sched_getaffinity( pid: i32, cpusetsize: usize, mask: *mut u64 ) -> i32;
+}
+
+//------------------------------------------------------------------------------
+//  現在のスレッドを指定したCPUにピン留め
+//------------------------------------------------------------------------------
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread( cpu: usize ) -> io::Result<()>
+{
+    if cpu >= CPU_SETSIZE
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid cpu: {}", cpu)));
+    }
+
+    let mut mask = [0_u64; CPU_SET_WORDS];
+    mask[cpu / 64] |= 1 << (cpu % 64);
+
+    //  SAFETY: `mask` は `cpu_set_t` と同じ大きさの有効なバッファ
+    let result = unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+    if result != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------------------
+//  現在のスレッドを指定したCPUにピン留め
+//------------------------------------------------------------------------------
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread( _cpu: usize ) -> io::Result<()>
+{
+    Err(io::Error::new(io::ErrorKind::Unsupported, "cpu affinity is not supported"))
+}
+
+//------------------------------------------------------------------------------
+//  現在のスレッドが実行を許可されたCPUのリスト
+//------------------------------------------------------------------------------
+#[cfg(target_os = "linux")]
+pub(crate) fn current_thread_cpus() -> io::Result<Vec<usize>>
+{
+    let mut mask = [0_u64; CPU_SET_WORDS];
+
+    //  SAFETY: `mask` は `cpu_set_t` と同じ大きさの有効なバッファ
+    let result = unsafe { sched_getaffinity(0, std::mem::size_of_val(&mask), mask.as_mut_ptr()) };
+    if result != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..CPU_SETSIZE).filter(|cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0).collect())
+}
+
+//------------------------------------------------------------------------------
+//  現在のスレッドが実行を許可されたCPUのリスト
+//------------------------------------------------------------------------------
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current_thread_cpus() -> io::Result<Vec<usize>>
+{
+    Err(io::Error::new(io::ErrorKind::Unsupported, "cpu affinity is not supported"))
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::CpuAffinity;
+
+    //--------------------------------------------------------------------------
+    //  test_cpu_for_slot
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_cpu_for_slot()
+    {
+        let round_robin = CpuAffinity::RoundRobin(vec![2, 5]);
+        assert_eq!(Some(2), round_robin.cpu_for_slot(0));
+        assert_eq!(Some(5), round_robin.cpu_for_slot(1));
+        assert_eq!(Some(2), round_robin.cpu_for_slot(2));
+
+        let explicit = CpuAffinity::Explicit(vec![3]);
+        assert_eq!(Some(3), explicit.cpu_for_slot(0));
+        assert_eq!(None, explicit.cpu_for_slot(1));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_pin_workers
+    //--------------------------------------------------------------------------
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_workers()
+    {
+        use super::current_thread_cpus;
+        use crate::threadpool::ThreadPool;
+        use core::time::Duration;
+
+        //  実行を許可されたCPUの中から1つを選んでピン留めする
+        let cpu = current_thread_cpus().unwrap()[0];
+        let pool = ThreadPool::builder("test")
+            .size(2)
+            .cpu_affinity(CpuAffinity::RoundRobin(vec![cpu]))
+            .panic_handler(|_, _| panic!("respawn worker"))
+            .build()
+            .unwrap();
+        for _ in 0..4
+        {
+            let cpus = pool.spawn(|| current_thread_cpus().unwrap());
+            assert_eq!(vec![cpu], cpus.join().unwrap());
+        }
+
+        //  再起動されたワーカーも同じCPUにピン留めされる
+        pool.schedule(|| panic!("job panicked"));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.stats().num_respawned < 1 || pool.num_live_threads() < 2
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        for _ in 0..4
+        {
+            let cpus = pool.spawn(|| current_thread_cpus().unwrap());
+            assert_eq!(vec![cpu], cpus.join().unwrap());
+        }
+    }
+    //--------------------------------------------------------------------------
+    //  test_pin_failure
+    //--------------------------------------------------------------------------
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_failure()
+    {
+        use super::{ current_thread_cpus, CPU_SETSIZE };
+        use crate::threadpool::ThreadPool;
+
+        //  実行を許可されていないCPUにはピン留めできない
+        let allowed = current_thread_cpus().unwrap();
+        let cpu = (0..CPU_SETSIZE).find(|cpu| !allowed.contains(cpu)).unwrap();
+        let pool = ThreadPool::builder("test")
+            .size(1)
+            .cpu_affinity(CpuAffinity::Explicit(vec![cpu]))
+            .build()
+            .unwrap();
+
+        //  ピン留めに失敗したワーカーもジョブを実行する
+        assert_eq!(1, pool.spawn(|| 1).join().unwrap());
+        assert_eq!(1, pool.stats().num_pin_failures);
+    }
+}
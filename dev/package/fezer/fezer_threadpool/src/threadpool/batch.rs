@@ -0,0 +1,225 @@
+/*
+
+    ジョブのバッチスケジュール
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    多数のジョブをまとめてキューに追加する。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 4).unwrap();
+    pool.schedule_batch(items.into_iter().map(|item| move || process(item)));
+
+    if let Err(e) = pool.try_schedule_batch(jobs)
+    {
+        let rejected: Vec<_> = e.into_inner().unwrap();
+    }
+    ```
+
+    ジョブを1つずつ `schedule()` する場合と異なり、スレッドの再起動のチェック、
+    キューのロック、待機中のワーカーの起床を、キューに空きがある間はバッチ全体
+    で1回だけ行う。
+
+    - `schedule_batch()` はキューが一杯になった時点で、空きができるまでスリープ
+      しながら残りのジョブを再試行する。プールが停止されていて追加できなかった
+      ジョブは戻り値で返す
+    - `try_schedule_batch()` はキューが一杯になった時点で、追加できなかったジョ
+      ブを `TryScheduleError::QueueFull` で返す。それより前のジョブはキューに追
+      加されたまま実行される
+
+    ジョブはイテレータの順にキューに追加される。
+
+*/
+
+use crate::error::{ StartThreadsError, TryScheduleError };
+use crate::threadpool::{ sleep_ms, ThreadPool };
+use crate::threadpool::priority::Priority;
+use crate::threadpool::queue::{ Job, ReserveError };
+
+use std::convert::Into;
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  キューの空きの分だけ先頭からジョブを追加
+    //  追加できなかったジョブは `jobs` に残る
+    //--------------------------------------------------------------------------
+    fn push_batch<F>( &self, jobs: &mut Vec<F>, priority: Priority ) -> Result<(), ReserveError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let num_reserved = self.inner.queue.reserve_many(jobs.len())?;
+        let reserved: Vec<Job> = jobs
+            .drain(..num_reserved)
+            .map(|f| Box::new(f) as Job)
+            .collect();
+        self.inner.queue.push_reserved_batch(reserved, priority);
+
+        if jobs.is_empty() { Ok(()) } else { Err(ReserveError::Full) }
+    }
+
+    //--------------------------------------------------------------------------
+    //  複数のジョブをまとめてスケジュール
+    //  キューが一杯の場合は空きができるまで待つ
+    //  プールが停止されていて追加できなかったジョブを返す
+    //--------------------------------------------------------------------------
+    pub fn schedule_batch<I, F>( &self, iter: I ) -> Vec<F>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() + Send + 'static,
+    {
+        let mut jobs: Vec<F> = iter.into_iter().collect();
+        while !jobs.is_empty()
+        {
+            //  スレッドの再起動処理
+            match self.inner.start_threads()
+            {
+                Ok(())
+                | Err(StartThreadsError::Respawn(_))
+                | Err(StartThreadsError::Parameter(_)) => {},
+                Err(StartThreadsError::LimitReached(_)) if self.num_live_threads() > 0 => {},
+
+                //  劣化状態ではジョブをキューに追加して、監視スレッドによる再起動
+                //  を待つ
+                Err(StartThreadsError::PoolDegraded(_)) => {},
+
+                //  他のプールが予算を使い切っている場合は、返却されるまで待って再
+                //  試行する。プール自身の上限に達している場合は待たずに追加する
+                Err(StartThreadsError::LimitReached(_)) =>
+                {
+                    if self.inner.wait_budget()
+                    {
+                        continue;
+                    }
+                },
+                Err(StartThreadsError::NoThreads(_)) =>
+                {
+                    sleep_ms(10);
+                    continue;
+                }
+            }
+
+            //  キューにジョブを送信
+            let result = self.push_batch(&mut jobs, Priority::Normal);
+            let _ignored = self.inner.grow_if_backlogged();
+            match result
+            {
+                Ok(()) => break,
+                //  停止したプールでは残りのジョブを返す
+                Err(ReserveError::Closed) => break,
+                //  キューがいっぱいだった場合はスリープしてから再試行
+                Err(ReserveError::Full) => sleep_ms(10),
+            }
+        }
+        jobs
+    }
+
+    //--------------------------------------------------------------------------
+    //  複数のジョブをまとめてスケジュール
+    //  キューが一杯の場合は追加できなかったジョブを返す
+    //--------------------------------------------------------------------------
+    pub fn try_schedule_batch<I, F>( &self, iter: I ) -> Result<(), TryScheduleError<Vec<F>>>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() + Send + 'static,
+    {
+        let mut jobs: Vec<F> = iter.into_iter().collect();
+        if jobs.is_empty()
+        {
+            return Ok(());
+        }
+
+        //  キューにジョブを送信
+        match self.push_batch(&mut jobs, Priority::Normal)
+        {
+            Ok(()) => {},
+            Err(ReserveError::Closed) => return Err(TryScheduleError::Aborted(jobs)),
+            Err(ReserveError::Full) =>
+            {
+                let _ignored = self.inner.start_threads().and_then(|()| self.inner.grow_if_backlogged());
+                return Err(TryScheduleError::QueueFull(jobs));
+            },
+        }
+        self.inner
+            .start_threads()
+            .and_then(|()| self.inner.grow_if_backlogged())
+            .map_err(Into::into)
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::error::TryScheduleError;
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+    use std::sync::{ Arc, Barrier };
+    use std::sync::mpsc::channel;
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_batch
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_batch()
+    {
+        //  キューの容量より多いジョブは空きを待って追加される
+        let pool = ThreadPool::builder("test").size(2).queue_capacity(8).build().unwrap();
+        let (sender, receiver) = channel();
+        pool.schedule_batch((0..100).map(|n|
+        {
+            let sender = sender.clone();
+            move || sender.send(n).unwrap()
+        }));
+        drop(sender);
+
+        let mut results: Vec<usize> = receiver.iter().take(100).collect();
+        results.sort_unstable();
+        assert_eq!((0..100).collect::<Vec<usize>>(), results);
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_try_schedule_batch
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_try_schedule_batch()
+    {
+        let pool = ThreadPool::builder("test").size(1).queue_capacity(4).build().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move || { barrier_clone.wait(); });
+
+        //  ワーカーが停止している間にキューを埋める
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.inner.queue.len() > 0
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+        let (sender, receiver) = channel();
+        let jobs: Vec<_> = (0..6).map(|n|
+        {
+            let sender = sender.clone();
+            move || sender.send(n).unwrap()
+        }).collect();
+        let rejected = match pool.try_schedule_batch(jobs)
+        {
+            Err(TryScheduleError::QueueFull(rejected)) => rejected,
+            _ => panic!("expected TryScheduleError::QueueFull"),
+        };
+        assert_eq!(2, rejected.len());
+        assert_eq!(4, pool.inner.queue.len());
+
+        //  追加されたジョブは順に実行される
+        barrier.wait();
+        let results: Vec<usize> = receiver.iter().take(4).collect();
+        assert_eq!(vec![0, 1, 2, 3], results);
+        assert_eq!(Ok(()), pool.try_schedule_batch(rejected));
+        let results: Vec<usize> = receiver.iter().take(2).collect();
+        assert_eq!(vec![4, 5], results);
+    }
+}
@@ -0,0 +1,228 @@
+/*
+
+    プロセス全体のスレッド数の予算
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    複数のスレッドプールで共有し、プール全体で起動できるスレッド数の上限を設け
+    る。ワーカースレッドは起動時に予算を1つ獲得し、停止時に返却する。
+
+    ```rust
+    let budget = fezer_threadpool::ThreadBudget::new(8);
+    let io_pool = fezer_threadpool::ThreadPool::builder("io")
+        .size_range(1, 8)
+        .thread_budget(&budget)
+        .build()
+        .unwrap();
+    let cpu_pool = fezer_threadpool::ThreadPool::builder("cpu")
+        .size(4)
+        .thread_budget(&budget)
+        .build()
+        .unwrap();
+    ```
+
+    予算が足りずにスレッドを起動できなかった場合は
+    `StartThreadsError::LimitReached` になる。スレッドが1つもないプールの
+    `schedule()` は、他のプールが予算を返却するまで待ってからジョブを追加する。
+
+*/
+
+use core::fmt::{ Debug, Formatter };
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Condvar, Mutex };
+
+//------------------------------------------------------------------------------
+//  予算の内部状態
+//------------------------------------------------------------------------------
+struct BudgetInner
+{
+    //  起動できるスレッド数の上限
+    limit: usize,
+
+    //  起動中のスレッド数
+    used: AtomicUsize,
+
+    //  予算の返却を通知する
+    released_lock: Mutex<()>,
+    released: Condvar,
+}
+
+//------------------------------------------------------------------------------
+//  ThreadBudget
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct ThreadBudget
+{
+    inner: Arc<BudgetInner>,
+}
+
+impl ThreadBudget
+{
+    //--------------------------------------------------------------------------
+    //  新しい予算を生成
+    //--------------------------------------------------------------------------
+    pub fn new( limit: usize ) -> Self
+    {
+        Self
+        {
+            inner: Arc::new(BudgetInner
+            {
+                limit,
+                used: AtomicUsize::new(0),
+                released_lock: Mutex::new(()),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  起動できるスレッド数の上限
+    //--------------------------------------------------------------------------
+    pub fn limit( &self ) -> usize
+    {
+        self.inner.limit
+    }
+
+    //--------------------------------------------------------------------------
+    //  予算を共有するプール全体で起動中のスレッド数
+    //--------------------------------------------------------------------------
+    pub fn used( &self ) -> usize
+    {
+        self.inner.used.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  追加で起動できるスレッド数
+    //--------------------------------------------------------------------------
+    pub fn available( &self ) -> usize
+    {
+        self.inner.limit.saturating_sub(self.used())
+    }
+
+    //--------------------------------------------------------------------------
+    //  予算を1つ獲得
+    //--------------------------------------------------------------------------
+    pub(crate) fn try_acquire( &self ) -> bool
+    {
+        self.inner
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used|
+            {
+                (used < self.inner.limit).then_some(used + 1)
+            })
+            .is_ok()
+    }
+
+    //--------------------------------------------------------------------------
+    //  予算を1つ返却
+    //--------------------------------------------------------------------------
+    pub(crate) fn release( &self )
+    {
+        self.inner.used.fetch_sub(1, Ordering::AcqRel);
+
+        //  返却を待っているスレッドに通知
+        let _released_lock = self.inner.released_lock.lock().unwrap();
+        self.inner.released.notify_all();
+    }
+
+    //--------------------------------------------------------------------------
+    //  予算に空きができるまで待つ
+    //--------------------------------------------------------------------------
+    pub(crate) fn wait_available( &self )
+    {
+        let mut released_lock = self.inner.released_lock.lock().unwrap();
+        while self.available() < 1
+        {
+            released_lock = self.inner.released.wait(released_lock).unwrap();
+        }
+    }
+}
+
+impl Debug for ThreadBudget
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "ThreadBudget{{used={:?}, limit={:?}}}", self.used(), self.limit())
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::ThreadBudget;
+    use crate::error::StartThreadsError;
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+
+    //--------------------------------------------------------------------------
+    //  test_shared_budget
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_shared_budget()
+    {
+        let budget = ThreadBudget::new(3);
+        let pool_a = ThreadPool::builder("a").size(2).thread_budget(&budget).build().unwrap();
+        assert_eq!(1, budget.available());
+
+        //  予算が足りない場合は起動できたスレッドだけで動作する
+        let pool_b = ThreadPool::builder("b").size_range(1, 2).thread_budget(&budget).build().unwrap();
+        assert_eq!(0, budget.available());
+        assert_eq!(Err(StartThreadsError::LimitReached(3)), pool_b.set_size(2));
+        assert_eq!(1, pool_b.num_live_threads());
+
+        //  プールの停止で予算が返却される
+        pool_a.join();
+        assert_eq!(2, budget.available());
+        assert_eq!(Ok(()), pool_b.set_size(2));
+        assert_eq!(2, pool_b.num_live_threads());
+        pool_b.join();
+        assert_eq!(3, budget.available());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_wait_budget
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_wait_budget()
+    {
+        let budget = ThreadBudget::new(1);
+        let pool_b = ThreadPool::builder("b").size_range(0, 1).thread_budget(&budget).build().unwrap();
+        let pool_a = ThreadPool::builder("a").size(1).thread_budget(&budget).build().unwrap();
+        assert_eq!(Err(StartThreadsError::LimitReached(1)), pool_b.set_size(1));
+        assert_eq!(0, pool_b.num_live_threads());
+
+        //  スレッドがないプールは予算が返却されるまで待ってからジョブを追加する
+        let releaser = std::thread::spawn(move ||
+        {
+            std::thread::sleep(Duration::from_millis(20));
+            pool_a.join();
+        });
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool_b.schedule(move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        releaser.join().unwrap();
+        assert_eq!(1, pool_b.num_live_threads());
+        pool_b.join();
+        assert_eq!(1, budget.available());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_thread_limit
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_thread_limit()
+    {
+        let pool = ThreadPool::builder("test").size(2).thread_limit(2).build().unwrap();
+        assert_eq!(2, pool.thread_limit());
+        assert_eq!(Err(StartThreadsError::LimitReached(2)), pool.set_size(4));
+        assert_eq!(2, pool.num_live_threads());
+    }
+}
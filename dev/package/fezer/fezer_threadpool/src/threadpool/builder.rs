@@ -0,0 +1,517 @@
+/*
+
+    スレッドプールのビルダ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `ThreadPool::new()` では指定できないスレッドプールの設定を行う。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPoolBuilder::new("worker")
+        .size(4)
+        .queue_capacity(1000)
+        .stack_size(4 * 1024 * 1024)
+        .thread_name(|n| format!("worker-{:02}", n))
+        .on_thread_start(|| init_logger())
+        .on_thread_stop(|| flush_logger())
+        .build()
+        .unwrap();
+    ```
+
+    `on_thread_start` と `on_thread_stop` は、パニック後に再起動されたスレッド
+    も含めてすべてのワーカースレッドで実行される。
+
+    `panic_handler` はジョブがパニックになったときに、パニックのペイロードとワ
+    ーカースレッドの名前を受け取る。`abort_on_panic` を指定すると、最初のパニッ
+    クでキューに残っているジョブを破棄してプールを停止する。
+
+    スレッドの起動に失敗した場合、監視スレッドが `restart_backoff` の初期値から
+    上限まで指数的に間隔を延ばしながら再試行する。起動の失敗が
+    `max_spawn_failures` 回続くとプールは劣化状態になり、`on_degraded` にスレッ
+    ド起動時のエラーが渡される。劣化状態でスレッドがない場合、`schedule()` はス
+    レッドの起動を待ち続けずにジョブをキューに追加して戻り、`try_schedule()` は
+    `TryScheduleError::PoolDegraded` を返す。スレッドの起動に成功すると劣化状態
+    は解除され、キューのジョブが実行される。
+
+*/
+
+use crate::error::NewThreadPoolError;
+use crate::threadpool::ThreadPool;
+use crate::threadpool::affinity::{ CpuAffinity, CPU_SETSIZE };
+use crate::threadpool::budget::ThreadBudget;
+use crate::threadpool::inner::Inner;
+
+use core::any::Any;
+use core::fmt::{ Debug, Formatter };
+use core::time::Duration;
+use std::io;
+use std::sync::Arc;
+
+//  スレッド名を生成するクロージャ
+pub(crate) type ThreadNameFn = Box<dyn Fn(usize) -> String + Send + Sync + 'static>;
+
+//  ワーカースレッドの開始時と停止時に実行されるクロージャ
+pub(crate) type ThreadHookFn = Arc<dyn Fn() + Send + Sync + 'static>;
+
+//  ジョブがパニックになったときに実行されるクロージャ
+pub(crate) type PanicHandlerFn = Arc<dyn Fn(&(dyn Any + Send), &str) + Send + Sync + 'static>;
+
+//  プールが劣化状態になったときに実行されるクロージャ
+pub(crate) type DegradedHandlerFn = Arc<dyn Fn(&io::Error) + Send + Sync + 'static>;
+
+//------------------------------------------------------------------------------
+//  ThreadPoolBuilder
+//------------------------------------------------------------------------------
+pub struct ThreadPoolBuilder
+{
+    //  プールの名前
+    pub(crate) name: String,
+
+    //  最小スレッド数
+    pub(crate) min_size: usize,
+
+    //  最大スレッド数
+    pub(crate) max_size: usize,
+
+    //  アイドル状態のワーカーを停止するまでの時間
+    pub(crate) keep_alive: Duration,
+
+    //  キューの上限
+    //  `None` の場合は最大スレッド数の200倍
+    pub(crate) queue_capacity: Option<usize>,
+
+    //  ワーカースレッドのスタックサイズ
+    pub(crate) stack_size: Option<usize>,
+
+    //  スレッド名を生成するクロージャ
+    pub(crate) thread_name: Option<ThreadNameFn>,
+
+    //  ワーカースレッドの開始時に実行されるクロージャ
+    pub(crate) on_thread_start: Option<ThreadHookFn>,
+
+    //  ワーカースレッドの停止時に実行されるクロージャ
+    pub(crate) on_thread_stop: Option<ThreadHookFn>,
+
+    //  起動できるスレッド数の上限
+    pub(crate) thread_limit: usize,
+
+    //  他のプールと共有するスレッド数の予算
+    pub(crate) budget: Option<ThreadBudget>,
+
+    //  ジョブがパニックになったときに実行されるクロージャ
+    pub(crate) panic_handler: Option<PanicHandlerFn>,
+
+    //  最初のパニックでプールを停止するかどうか
+    pub(crate) abort_on_panic: bool,
+
+    //  ワーカーへのCPUの割り当て方
+    pub(crate) cpu_affinity: Option<CpuAffinity>,
+
+    //  スレッドの起動を再試行する間隔の初期値と上限
+    pub(crate) restart_backoff: (Duration, Duration),
+
+    //  劣化状態とみなすスレッド起動の連続失敗回数
+    pub(crate) max_spawn_failures: usize,
+
+    //  プールが劣化状態になったときに実行されるクロージャ
+    pub(crate) on_degraded: Option<DegradedHandlerFn>,
+}
+
+impl ThreadPoolBuilder
+{
+    //--------------------------------------------------------------------------
+    //  新しいビルダを生成
+    //--------------------------------------------------------------------------
+    pub fn new( name: impl Into<String> ) -> Self
+    {
+        Self
+        {
+            name: name.into(),
+            min_size: 1,
+            max_size: 1,
+            keep_alive: Duration::from_millis(500),
+            queue_capacity: None,
+            stack_size: None,
+            thread_name: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+            thread_limit: usize::MAX,
+            budget: None,
+            panic_handler: None,
+            abort_on_panic: false,
+            cpu_affinity: None,
+            restart_backoff: (Duration::from_millis(10), Duration::from_secs(1)),
+            max_spawn_failures: 10,
+            on_degraded: None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数を固定
+    //--------------------------------------------------------------------------
+    pub fn size( mut self, size: usize ) -> Self
+    {
+        self.min_size = size;
+        self.max_size = size;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数の範囲を指定して、伸縮するプールにする
+    //--------------------------------------------------------------------------
+    pub fn size_range( mut self, min_size: usize, max_size: usize ) -> Self
+    {
+        self.min_size = min_size;
+        self.max_size = max_size;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  アイドル状態のワーカーを停止するまでの時間
+    //--------------------------------------------------------------------------
+    pub fn keep_alive( mut self, keep_alive: Duration ) -> Self
+    {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューに格納できるジョブ数の上限
+    //--------------------------------------------------------------------------
+    pub fn queue_capacity( mut self, capacity: usize ) -> Self
+    {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューのジョブ数を無制限にする
+    //--------------------------------------------------------------------------
+    pub fn unbounded_queue( mut self ) -> Self
+    {
+        self.queue_capacity = Some(usize::MAX);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドのスタックサイズ
+    //--------------------------------------------------------------------------
+    pub fn stack_size( mut self, stack_size: usize ) -> Self
+    {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド名を生成するクロージャ
+    //  引数はプール内で起動したスレッドの通し番号
+    //--------------------------------------------------------------------------
+    pub fn thread_name<F>( mut self, f: F ) -> Self
+    where
+        F: Fn(usize) -> String + Send + Sync + 'static,
+    {
+        self.thread_name = Some(Box::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドの開始時に実行されるクロージャ
+    //--------------------------------------------------------------------------
+    pub fn on_thread_start<F>( mut self, f: F ) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドの停止時に実行されるクロージャ
+    //--------------------------------------------------------------------------
+    pub fn on_thread_stop<F>( mut self, f: F ) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_stop = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールで起動できるスレッド数の上限
+    //  `set_size()` などで上限を超えて起動しようとするとエラーになる
+    //--------------------------------------------------------------------------
+    pub fn thread_limit( mut self, limit: usize ) -> Self
+    {
+        self.thread_limit = limit;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  他のプールと共有するスレッド数の予算
+    //--------------------------------------------------------------------------
+    pub fn thread_budget( mut self, budget: &ThreadBudget ) -> Self
+    {
+        self.budget = Some(budget.clone());
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブがパニックになったときに実行されるクロージャ
+    //  引数はパニックのペイロードとワーカースレッドの名前
+    //--------------------------------------------------------------------------
+    pub fn panic_handler<F>( mut self, f: F ) -> Self
+    where
+        F: Fn(&(dyn Any + Send), &str) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  最初のパニックでキューに残っているジョブを破棄してプールを停止する
+    //--------------------------------------------------------------------------
+    pub fn abort_on_panic( mut self, abort_on_panic: bool ) -> Self
+    {
+        self.abort_on_panic = abort_on_panic;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドをCPUにピン留めする
+    //--------------------------------------------------------------------------
+    pub fn cpu_affinity( mut self, affinity: CpuAffinity ) -> Self
+    {
+        self.cpu_affinity = Some(affinity);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドの起動を再試行する間隔の初期値と上限
+    //--------------------------------------------------------------------------
+    pub fn restart_backoff( mut self, initial: Duration, max: Duration ) -> Self
+    {
+        self.restart_backoff = (initial, max);
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  劣化状態とみなすスレッド起動の連続失敗回数
+    //--------------------------------------------------------------------------
+    pub fn max_spawn_failures( mut self, max_spawn_failures: usize ) -> Self
+    {
+        self.max_spawn_failures = max_spawn_failures;
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールが劣化状態になったときに実行されるクロージャ
+    //  引数は最後のスレッド起動時のエラー
+    //--------------------------------------------------------------------------
+    pub fn on_degraded<F>( mut self, f: F ) -> Self
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_degraded = Some(Arc::new(f));
+        self
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドプールを生成
+    //--------------------------------------------------------------------------
+    pub fn build( self ) -> Result<ThreadPool, NewThreadPoolError>
+    {
+        //  名前が指定されていなかった場合
+        if self.name.is_empty()
+        {
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    "ThreadPoolBuilder::build called with empty name".to_string()
+                )
+            )
+        }
+
+        //  スレッド数の範囲が不正だった場合
+        if self.max_size < 1 || self.min_size > self.max_size
+        {
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    format!
+                    (
+                        "ThreadPoolBuilder::build called with invalid size range: {:?}..={:?}",
+                        self.min_size,
+                        self.max_size
+                    )
+                )
+            )
+        }
+
+        //  キューの上限が0だった場合
+        if self.queue_capacity == Some(0)
+        {
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    "ThreadPoolBuilder::build called with zero queue capacity".to_string()
+                )
+            )
+        }
+
+        //  CPUの指定が不正だった場合
+        if let Some(affinity) = &self.cpu_affinity
+        {
+            let cpus = affinity.cpus();
+            if cpus.is_empty() || cpus.iter().any(|&cpu| cpu >= CPU_SETSIZE)
+            {
+                return Err
+                (
+                    NewThreadPoolError::Parameter
+                    (
+                        format!("ThreadPoolBuilder::build called with invalid cpu affinity: {:?}", affinity)
+                    )
+                )
+            }
+        }
+
+        //  再試行の間隔が不正だった場合
+        let (initial, max) = self.restart_backoff;
+        if initial.is_zero() || initial > max
+        {
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    format!
+                    (
+                        "ThreadPoolBuilder::build called with invalid restart backoff: {:?}..={:?}",
+                        initial,
+                        max
+                    )
+                )
+            )
+        }
+
+        //  連続失敗回数が0だった場合
+        if self.max_spawn_failures == 0
+        {
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    "ThreadPoolBuilder::build called with zero max spawn failures".to_string()
+                )
+            )
+        }
+
+        let pool = ThreadPool
+        {
+            inner: Arc::new(Inner::new(self)),
+        };
+
+        //  スレッドの起動
+        pool.inner.start_threads()?;
+
+        Ok(pool)
+    }
+}
+
+impl Debug for ThreadPoolBuilder
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!
+        (
+            f,
+            "ThreadPoolBuilder{{{:?}, size={:?}..={:?}}}",
+            self.name,
+            self.min_size,
+            self.max_size
+        )
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::ThreadPoolBuilder;
+    use crate::error::NewThreadPoolError;
+    use crate::threadpool::affinity::CpuAffinity;
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    //--------------------------------------------------------------------------
+    //  test_builder
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_builder()
+    {
+        let num_started = Arc::new(AtomicUsize::new(0));
+        let num_stopped = Arc::new(AtomicUsize::new(0));
+        let num_started_clone = num_started.clone();
+        let num_stopped_clone = num_stopped.clone();
+
+        let prefix = String::from("custom");
+        let pool = ThreadPoolBuilder::new("test")
+            .size(3)
+            .unbounded_queue()
+            .stack_size(256 * 1024)
+            .thread_name(move |n| format!("{}-{:02}", prefix, n))
+            .on_thread_start(move || { num_started_clone.fetch_add(1, Ordering::AcqRel); })
+            .on_thread_stop(move || { num_stopped_clone.fetch_add(1, Ordering::AcqRel); })
+            .panic_handler(|_, _| panic!("panic handler panicked"))
+            .build()
+            .unwrap();
+
+        let name = pool.spawn(|| std::thread::current().name().unwrap().to_string());
+        assert!(name.join().unwrap().starts_with("custom-0"));
+
+        //  パニックで再起動されたスレッドでもフックが実行される
+        pool.schedule(|| panic!("job panicked"));
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while num_started.load(Ordering::Acquire) < 4
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        pool.join();
+        assert_eq!(4, num_started.load(Ordering::Acquire));
+        assert_eq!(4, num_stopped.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_builder_invalid
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_builder_invalid()
+    {
+        assert_eq!
+        (
+            NewThreadPoolError::Parameter
+            (
+                "ThreadPoolBuilder::build called with empty name".to_string()
+            ),
+            ThreadPoolBuilder::new("").build().unwrap_err()
+        );
+        assert!(ThreadPoolBuilder::new("test").size_range(2, 1).build().is_err());
+        assert!(ThreadPoolBuilder::new("test").queue_capacity(0).build().is_err());
+        assert!
+        (
+            ThreadPoolBuilder::new("test")
+                .cpu_affinity(CpuAffinity::Explicit(vec![]))
+                .build()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,333 @@
+/*
+
+    ジョブのキャンセル
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    キャンセル可能なジョブをスケジュールする。
+
+    ```rust
+    let request = CancellationToken::new();
+    let handle = pool.schedule_with_token(&request, |token|
+    {
+        for chunk in chunks
+        {
+            if token.is_cancelled() { return; }
+            process(chunk);
+        }
+    });
+
+    handle.cancel();    //  このジョブだけをキャンセル
+    request.cancel();   //  リクエストに属するジョブをすべてキャンセル
+    ```
+
+    - 開始前にキャンセルされたジョブは実行されずに破棄される
+    - 実行中のジョブは渡された `CancellationToken` を確認して、協調的に処理を中
+      断する
+
+    `CancellationToken` は親子の木構造を作る。親をキャンセルすると子孫のトーク
+    ンもすべてキャンセルされるが、子をキャンセルしても親には影響しない。キャン
+    セル済みのトークンから作った子は、最初からキャンセルされている。
+
+    `CancelHandle::cancel()` は開始前のジョブのクロージャをその場で破棄する。親
+    のトークンからキャンセルされた場合は、ワーカーがジョブを取り出した時点で破
+    棄する。
+
+*/
+
+use crate::threadpool::ThreadPool;
+
+use core::fmt::{ Debug, Formatter };
+use core::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, Mutex, Weak };
+
+//------------------------------------------------------------------------------
+//  トークンの共有状態
+//------------------------------------------------------------------------------
+#[derive(Default)]
+struct TokenInner
+{
+    //  キャンセルされたかどうか
+    cancelled: AtomicBool,
+
+    //  子のトークン
+    children: Mutex<Vec<Weak<TokenInner>>>,
+}
+
+//------------------------------------------------------------------------------
+//  CancellationToken
+//------------------------------------------------------------------------------
+#[derive(Clone, Default)]
+pub struct CancellationToken
+{
+    //  トークンの共有状態
+    inner: Arc<TokenInner>,
+}
+
+impl CancellationToken
+{
+    //--------------------------------------------------------------------------
+    //  新しいトークンを生成
+    //--------------------------------------------------------------------------
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    //--------------------------------------------------------------------------
+    //  子のトークンを生成
+    //--------------------------------------------------------------------------
+    pub fn child_token( &self ) -> Self
+    {
+        let child = Self::new();
+
+        //  キャンセルと同じロックの内側で確認して、子の登録漏れを防ぐ
+        let mut children = self.inner.children.lock().unwrap();
+        if self.is_cancelled()
+        {
+            child.inner.cancelled.store(true, Ordering::Release);
+            return child;
+        }
+
+        //  ドロップされた子は、配列を拡張する前に取り除く
+        if children.len() == children.capacity()
+        {
+            children.retain(|weak| weak.strong_count() > 0);
+        }
+        children.push(Arc::downgrade(&child.inner));
+        child
+    }
+
+    //--------------------------------------------------------------------------
+    //  自身と子孫のトークンをすべてキャンセル
+    //--------------------------------------------------------------------------
+    pub fn cancel( &self )
+    {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+
+        let children = core::mem::take(&mut *self.inner.children.lock().unwrap());
+        for inner in children.iter().filter_map(Weak::upgrade)
+        {
+            CancellationToken { inner }.cancel();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キャンセルされたかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_cancelled( &self ) -> bool
+    {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+}
+
+impl Debug for CancellationToken
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "CancellationToken{{cancelled={}}}", self.is_cancelled())
+    }
+}
+
+//  開始前のジョブのクロージャ
+type PendingJob = Box<dyn FnOnce(&CancellationToken) + Send + 'static>;
+
+//------------------------------------------------------------------------------
+//  CancelHandle
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct CancelHandle
+{
+    //  ジョブに渡すトークン
+    token: CancellationToken,
+
+    //  開始前のジョブ
+    //  ワーカーが取り出すか、キャンセルで破棄すると `None` になる
+    pending: Arc<Mutex<Option<PendingJob>>>,
+}
+
+impl CancelHandle
+{
+    //--------------------------------------------------------------------------
+    //  ジョブをキャンセル
+    //  開始前のジョブを破棄した場合は `true` を返す
+    //--------------------------------------------------------------------------
+    pub fn cancel( &self ) -> bool
+    {
+        self.token.cancel();
+        let pending = self.pending.lock().unwrap().take();
+        pending.is_some()
+    }
+
+    //--------------------------------------------------------------------------
+    //  キャンセルされたかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_cancelled( &self ) -> bool
+    {
+        self.token.is_cancelled()
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブに渡すトークン
+    //--------------------------------------------------------------------------
+    pub fn token( &self ) -> &CancellationToken
+    {
+        &self.token
+    }
+}
+
+impl Debug for CancelHandle
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "CancelHandle{{{:?}}}", self.token)
+    }
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  キャンセル可能なジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_cancellable<F>( &self, f: F ) -> CancelHandle
+    where
+        F: FnOnce(&CancellationToken) + Send + 'static,
+    {
+        self.schedule_with_token(&CancellationToken::new(), f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  親のトークンを指定してキャンセル可能なジョブをスケジュール
+    //  ジョブには親の子のトークンが渡される
+    //--------------------------------------------------------------------------
+    pub fn schedule_with_token<F>( &self, parent: &CancellationToken, f: F ) -> CancelHandle
+    where
+        F: FnOnce(&CancellationToken) + Send + 'static,
+    {
+        let token = parent.child_token();
+        let pending: Arc<Mutex<Option<PendingJob>>> = Arc::new(Mutex::new(Some(Box::new(f))));
+        let handle = CancelHandle { token: token.clone(), pending: pending.clone() };
+
+        self.schedule(move ||
+        {
+            let Some(f) = pending.lock().unwrap().take() else { return };
+            if !token.is_cancelled()
+            {
+                f(&token);
+            }
+        });
+        handle
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::CancellationToken;
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+    use std::sync::{ Arc, Barrier };
+    use std::sync::mpsc::channel;
+
+    //--------------------------------------------------------------------------
+    //  test_token_tree
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_token_tree()
+    {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+        let sibling = root.child_token();
+
+        //  子のキャンセルは親と兄弟に影響しない
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert!(!sibling.is_cancelled());
+
+        root.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(root.child_token().is_cancelled());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_cancel_pending_job
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_cancel_pending_job()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move || { barrier_clone.wait(); });
+
+        //  ワーカーが停止している間にジョブをキャンセル
+        let (sender, receiver) = channel();
+        let marker = Arc::new(());
+        let marker_clone = marker.clone();
+        let sender_clone = sender.clone();
+        let handle = pool.schedule_cancellable(move |_|
+        {
+            drop(marker_clone);
+            sender_clone.send("cancelled job").unwrap();
+        });
+        let request = CancellationToken::new();
+        let sender_clone = sender.clone();
+        pool.schedule_with_token(&request, move |_| sender_clone.send("request job").unwrap());
+        pool.schedule(move || sender.send("last job").unwrap());
+
+        assert!(handle.cancel());
+        assert!(handle.is_cancelled());
+        assert_eq!(1, Arc::strong_count(&marker));
+        assert!(!handle.cancel());
+        request.cancel();
+
+        barrier.wait();
+        assert_eq!("last job", receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_cancel_running_job
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_cancel_running_job()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let (started_sender, started_receiver) = channel();
+        let (sender, receiver) = channel();
+        let request = CancellationToken::new();
+        pool.schedule_with_token(&request, move |token|
+        {
+            started_sender.send(()).unwrap();
+            let mut num_iterations = 0_usize;
+            while !token.is_cancelled()
+            {
+                num_iterations += 1;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            sender.send(num_iterations).unwrap();
+        });
+
+        //  実行中のジョブは親のキャンセルを確認して終了する
+        started_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        request.cancel();
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
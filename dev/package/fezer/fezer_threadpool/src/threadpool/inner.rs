@@ -2,140 +2,717 @@
 
     スレッドのコレクション
 
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    ワーカースレッドはそれぞれスロットを1つ占有し、スロットのインデックスがロー
+    カルキューのインデックスになる。パニックで停止したワーカーのスロットは解放
+    され、再起動されたワーカーが引き継ぐ。スロットはジョブを実行中かどうかと累
+    積の実行時間を記録し、統計情報として参照される。
+
+    # スレッド数の伸縮
+
+    スレッド数は `min_size` から `max_size` の範囲で伸縮する。
+
+    - 停止したスレッドは `min_size` に達するまで再起動する
+    - ジョブの追加時に、キューに溜まっているジョブが待機中のワーカーより多けれ
+      ば `max_size` に達するまでスレッドを追加する
+    - `keep_alive` の間ジョブを受信しなかったワーカーは、スレッド数が
+      `min_size` を超えていれば停止する。スレッド数が `min_size` 以下の間は、
+      アイドル状態のワーカーはタイムアウトせずに待機する
+    - スレッド数が `max_size` を超えている場合は、ジョブの完了後に停止する
+
+    固定サイズのプールは `min_size` と `max_size` が等しいプールとして扱う。
+
+    # 劣化状態
+
+    スレッドの起動に `max_spawn_failures` 回続けて失敗すると、プールは劣化状態に
+    なる。劣化状態になったときに `on_degraded` を実行し、監視スレッドにバックオ
+    フしながら再起動を続けさせる。スレッドの起動に1回でも成功すると、劣化状態
+    は解除される。
+
 */
 
 use crate::error::StartThreadsError;
 use crate::atomic_counter::AtomicCounter;
+use crate::threadpool::affinity::{ pin_current_thread, CpuAffinity };
+use crate::threadpool::budget::ThreadBudget;
+use crate::threadpool::builder::
+{
+    DegradedHandlerFn,
+    PanicHandlerFn,
+    ThreadHookFn,
+    ThreadNameFn,
+    ThreadPoolBuilder,
+};
+use crate::threadpool::queue::{ JobQueue, Pop };
+use crate::threadpool::supervisor::Supervisor;
+use crate::threadpool::timer::Timer;
 
-use core::sync::atomic::AtomicUsize;
+use core::any::Any;
+use core::sync::atomic::{ AtomicBool, AtomicU64, AtomicUsize, Ordering };
 use core::time::Duration;
-use std::sync::{ Arc, Mutex };
-use std::sync::mpsc::{ Receiver, RecvTimeoutError };
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::sync::{ Arc, Condvar, Mutex, RwLock };
+use std::time::Instant;
+
+//------------------------------------------------------------------------------
+//  ワーカーのスロット
+//------------------------------------------------------------------------------
+pub(crate) struct Slot
+{
+    //  ワーカーが使用中かどうか
+    pub(crate) in_use: AtomicBool,
 
-//  スレッド数の上限
-pub static INTERNAL_MAX_THREADS: AtomicUsize = AtomicUsize::new(usize::MAX);
+    //  ジョブを実行中かどうか
+    pub(crate) busy: AtomicBool,
+
+    //  ジョブの実行に費やした累積時間（ナノ秒）
+    pub(crate) busy_nanos: AtomicU64,
+}
+
+impl Slot
+{
+    //--------------------------------------------------------------------------
+    //  新しいスロットを生成
+    //--------------------------------------------------------------------------
+    fn new() -> Self
+    {
+        Self
+        {
+            in_use: AtomicBool::new(false),
+            busy: AtomicBool::new(false),
+            busy_nanos: AtomicU64::new(0),
+        }
+    }
+}
 
 //------------------------------------------------------------------------------
 //  Inner
 //------------------------------------------------------------------------------
 pub(crate) struct Inner
 {
-    //  スレッドに関連付けられた名前
-    pub(crate) name: &'static str,
+    //  プールの名前
+    pub(crate) name: String,
 
-    //  nameのサフィックスとして付与されるカウント
+    //  スレッド名の生成に用いるカウント
     pub(crate) next_name_num: AtomicCounter,
 
-    //  スレッド数
-    pub(crate) size: usize,
+    //  スレッド名を生成するクロージャ
+    thread_name: Option<ThreadNameFn>,
+
+    //  ワーカースレッドのスタックサイズ
+    stack_size: Option<usize>,
+
+    //  ワーカーへのCPUの割り当て方
+    cpu_affinity: Option<CpuAffinity>,
+
+    //  ワーカースレッドの開始時と停止時に実行されるクロージャ
+    on_thread_start: Option<ThreadHookFn>,
+    on_thread_stop: Option<ThreadHookFn>,
+
+    //  起動できるスレッド数の上限
+    thread_limit: usize,
+
+    //  他のプールと共有するスレッド数の予算
+    budget: Option<ThreadBudget>,
+
+    //  ジョブがパニックになったときに実行されるクロージャ
+    panic_handler: Option<PanicHandlerFn>,
+
+    //  最初のパニックでプールを停止するかどうか
+    abort_on_panic: bool,
+
+    //  完了したジョブの数
+    pub(crate) num_completed: AtomicCounter,
+
+    //  パニックになったジョブの数
+    pub(crate) num_panicked: AtomicCounter,
+
+    //  パニックで停止して再起動の対象になったワーカーの数
+    pub(crate) num_respawned: AtomicCounter,
 
-    //  ジョブのReceiver
-    //  複数のスレッドからアクセスされる可能性があるので排他制御が必要
-    pub(crate) receiver: Mutex<Receiver<Box<dyn FnOnce() + Send>>>,
+    //  CPUへのピン留めに失敗したワーカーの数
+    pub(crate) num_pin_failures: AtomicCounter,
+
+    //  パニックによってプールが停止されたかどうか
+    aborted: AtomicBool,
+
+    //  最小スレッド数
+    min_size: AtomicUsize,
+
+    //  最大スレッド数
+    max_size: AtomicUsize,
+
+    //  アイドル状態のワーカーを停止するまでの時間
+    keep_alive: Duration,
+
+    //  ジョブのキュー
+    pub(crate) queue: JobQueue,
+
+    //  遅延ジョブと周期ジョブのタイマー
+    pub(crate) timer: Timer,
+
+    //  停止したワーカーを再起動する監視
+    supervisor: Supervisor,
+
+    //  スレッドの起動を再試行する間隔の初期値と上限
+    restart_backoff: (Duration, Duration),
+
+    //  劣化状態とみなすスレッド起動の連続失敗回数
+    max_spawn_failures: usize,
+
+    //  プールが劣化状態になったときに実行されるクロージャ
+    on_degraded: Option<DegradedHandlerFn>,
+
+    //  スレッド起動の連続失敗回数
+    num_spawn_failures: AtomicUsize,
+
+    //  スレッドの起動に続けて失敗して劣化状態になっているかどうか
+    degraded: AtomicBool,
+
+    //  ワーカーのスロット
+    pub(crate) slots: RwLock<Vec<Slot>>,
+
+    //  生存中のスレッドの数
+    num_live: AtomicUsize,
+
+    //  スレッドの停止を通知する
+    stopped_lock: Mutex<()>,
+    stopped: Condvar,
+}
+
+//------------------------------------------------------------------------------
+//  ワーカーの終了時にスロットを解放する
+//  パニックで停止した場合は代わりのスレッドを起動する
+//------------------------------------------------------------------------------
+struct WorkerGuard<'a>
+{
+    inner: &'a Arc<Inner>,
+    index: usize,
+
+    //  スレッド数を既に減らしているかどうか
+    retired: bool,
+}
+
+impl Drop for WorkerGuard<'_>
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.inner.queue.unregister_worker();
+        if let Some(on_thread_stop) = &self.inner.on_thread_stop
+        {
+            on_thread_stop();
+        }
+        if let Some(budget) = &self.inner.budget
+        {
+            budget.release();
+        }
+        if !self.retired
+        {
+            self.inner.num_live.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.inner.release_slot(self.index);
+        if std::thread::panicking()
+        {
+            self.inner.num_respawned.next();
+            self.inner.supervisor.request_respawn(self.inner);
+        }
+
+        //  停止を待っているスレッドに通知
+        let _stopped_lock = self.inner.stopped_lock.lock().unwrap();
+        self.inner.stopped.notify_all();
+    }
 }
 
 impl Inner
 {
+    //--------------------------------------------------------------------------
+    //  新しいInnerを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( builder: ThreadPoolBuilder ) -> Self
+    {
+        let max_size = builder.max_size;
+        let capacity = builder.queue_capacity.unwrap_or(max_size.saturating_mul(200));
+        Self
+        {
+            name: builder.name,
+            next_name_num: AtomicCounter::new(),
+            thread_name: builder.thread_name,
+            stack_size: builder.stack_size,
+            cpu_affinity: builder.cpu_affinity,
+            on_thread_start: builder.on_thread_start,
+            on_thread_stop: builder.on_thread_stop,
+            thread_limit: builder.thread_limit,
+            budget: builder.budget,
+            panic_handler: builder.panic_handler,
+            abort_on_panic: builder.abort_on_panic,
+            num_completed: AtomicCounter::new(),
+            num_panicked: AtomicCounter::new(),
+            num_respawned: AtomicCounter::new(),
+            num_pin_failures: AtomicCounter::new(),
+            aborted: AtomicBool::new(false),
+            min_size: AtomicUsize::new(builder.min_size),
+            max_size: AtomicUsize::new(max_size),
+            keep_alive: builder.keep_alive,
+            queue: JobQueue::new(max_size, capacity),
+            timer: Timer::new(),
+            supervisor: Supervisor::new(),
+            restart_backoff: builder.restart_backoff,
+            max_spawn_failures: builder.max_spawn_failures,
+            on_degraded: builder.on_degraded,
+            num_spawn_failures: AtomicUsize::new(0),
+            degraded: AtomicBool::new(false),
+            slots: RwLock::new((0..max_size).map(|_| Slot::new()).collect()),
+            num_live: AtomicUsize::new(0),
+            stopped_lock: Mutex::new(()),
+            stopped: Condvar::new(),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  最小スレッド数
+    //--------------------------------------------------------------------------
+    pub(crate) fn min_size( &self ) -> usize
+    {
+        self.min_size.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  最大スレッド数
+    //--------------------------------------------------------------------------
+    pub(crate) fn max_size( &self ) -> usize
+    {
+        self.max_size.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  起動できるスレッド数の上限
+    //--------------------------------------------------------------------------
+    pub(crate) fn thread_limit( &self ) -> usize
+    {
+        self.thread_limit
+    }
+
+    //--------------------------------------------------------------------------
+    //  アイドル状態のワーカーを停止するまでの時間
+    //--------------------------------------------------------------------------
+    pub(crate) fn keep_alive( &self ) -> Duration
+    {
+        self.keep_alive
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数の範囲を変更
+    //--------------------------------------------------------------------------
+    pub(crate) fn set_size_range( self: &Arc<Self>, min_size: usize, max_size: usize )
+        -> Result<(), StartThreadsError>
+    {
+        //  スロットとローカルキューを先に確保
+        self.queue.grow(max_size);
+        {
+            let mut slots = self.slots.write().unwrap();
+            while slots.len() < max_size
+            {
+                slots.push(Slot::new());
+            }
+        }
+
+        self.min_size.store(min_size, Ordering::Release);
+        self.max_size.store(max_size, Ordering::Release);
+
+        //  超過したワーカーはジョブを受信したときかアイドル時に停止する
+        //  すぐに停止できるように待機中のワーカーを起床させる
+        self.queue.notify_all();
+        self.start_threads()
+    }
+
     //--------------------------------------------------------------------------
     //  生存中のスレッドの数
     //--------------------------------------------------------------------------
-    pub(crate) fn num_live_threads( self: &Arc<Self> ) -> usize
+    pub(crate) fn num_live_threads( &self ) -> usize
+    {
+        self.num_live.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  パニックによってプールが停止されたかどうか
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_aborted( &self ) -> bool
+    {
+        self.aborted.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドの起動を再試行する間隔の初期値と上限
+    //--------------------------------------------------------------------------
+    pub(crate) fn restart_backoff( &self ) -> (Duration, Duration)
+    {
+        self.restart_backoff
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドの起動に続けて失敗して劣化状態になっているかどうか
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_degraded( &self ) -> bool
+    {
+        self.degraded.load(Ordering::Acquire)
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドの起動の失敗を記録
+    //  連続失敗回数が上限に達した場合は劣化状態にして通知し、監視スレッドに再試
+    //  行させる
+    //--------------------------------------------------------------------------
+    fn spawn_failed( self: &Arc<Self>, e: &std::io::Error ) -> bool
+    {
+        let num_failures = self.num_spawn_failures.fetch_add(1, Ordering::AcqRel) + 1;
+        if num_failures < self.max_spawn_failures
+        {
+            return false;
+        }
+
+        if !self.degraded.swap(true, Ordering::AcqRel)
+        {
+            if let Some(on_degraded) = &self.on_degraded
+            {
+                on_degraded(e);
+            }
+            self.supervisor.request_respawn(self);
+        }
+        true
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブのパニックを記録して通知
+    //--------------------------------------------------------------------------
+    fn job_panicked( &self, payload: Box<dyn Any + Send + 'static> )
+    {
+        self.num_panicked.next();
+
+        if let Some(panic_handler) = &self.panic_handler
+        {
+            let thread = std::thread::current();
+            panic_handler(payload.as_ref(), thread.name().unwrap_or_default());
+        }
+
+        //  キューを閉じて残りのジョブを破棄
+        //  スコープのジョブは破棄されたときに完了が記録され、スコープは終了する
+        if self.abort_on_panic && !self.aborted.swap(true, Ordering::AcqRel)
+        {
+            self.close();
+            self.queue.clear();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューとタイマーと監視を閉じる
+    //--------------------------------------------------------------------------
+    pub(crate) fn close( &self )
     {
-        Arc::strong_count(self) - 1
+        self.supervisor.close();
+        self.timer.close();
+        self.queue.close();
+    }
+
+    //--------------------------------------------------------------------------
+    //  すべてのスレッドが停止するまで待つ
+    //  期限までに停止しなかった場合は `false` を返す
+    //--------------------------------------------------------------------------
+    pub(crate) fn wait_stopped( &self, deadline: Option<Instant> ) -> bool
+    {
+        let mut stopped_lock = self.stopped_lock.lock().unwrap();
+        while self.num_live_threads() > 0
+        {
+            match deadline
+            {
+                None => stopped_lock = self.stopped.wait(stopped_lock).unwrap(),
+                Some(deadline) =>
+                {
+                    let now = Instant::now();
+                    if deadline <= now
+                    {
+                        return false;
+                    }
+                    stopped_lock = self.stopped.wait_timeout(stopped_lock, deadline - now).unwrap().0;
+                },
+            }
+        }
+        true
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数が上限未満であれば、空いているスロットを確保
+    //--------------------------------------------------------------------------
+    fn claim_slot( &self, limit: usize ) -> Option<usize>
+    {
+        self.num_live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < limit).then_some(n + 1))
+            .ok()?;
+
+        let index = self.slots.read().unwrap().iter().position(|slot|
+        {
+            slot.in_use.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_ok()
+        });
+        if index.is_none()
+        {
+            //  停止中のワーカーがまだスロットを解放していない場合
+            self.num_live.fetch_sub(1, Ordering::AcqRel);
+        }
+        index
+    }
+
+    //--------------------------------------------------------------------------
+    //  スロットを解放
+    //--------------------------------------------------------------------------
+    fn release_slot( &self, index: usize )
+    {
+        self.slots.read().unwrap()[index].in_use.store(false, Ordering::Release);
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数が上限を超えていれば、スレッド数を1つ減らす
+    //--------------------------------------------------------------------------
+    fn try_retire( &self, limit: usize ) -> bool
+    {
+        self.num_live
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n > limit).then(|| n - 1))
+            .is_ok()
     }
 
     //--------------------------------------------------------------------------
     //  スレッド生成時に実行される処理
     //--------------------------------------------------------------------------
-    fn work( self: &Arc<Self> )
+    fn work( self: &Arc<Self>, index: usize, cpu: Option<usize> )
     {
+        //  ピン留めに失敗した場合は記録して、ピン留めせずに動作する
+        if let Some(cpu) = cpu
+        {
+            if pin_current_thread(cpu).is_err()
+            {
+                self.num_pin_failures.next();
+            }
+        }
+
+        let mut worker = WorkerGuard { inner: self, index, retired: false };
+        self.queue.register_worker(index);
+        if let Some(on_thread_start) = &self.on_thread_start
+        {
+            on_thread_start();
+        }
+
         loop
         {
-            //  ジョブを受信
-            let recv_result = self
-                .receiver
-                .lock()
-                .unwrap()
-                .recv_timeout(Duration::from_millis(500));
+            //  最大スレッド数が縮小された場合は停止
+            if self.try_retire(self.max_size())
+            {
+                worker.retired = true;
+                return;
+            }
+
+            //  最小スレッド数を超えている場合だけタイムアウトを設定して待機
+            //  それ以外はジョブの追加か停止の通知があるまで起床しない
+            let timeout = (self.num_live_threads() > self.min_size()).then_some(self.keep_alive);
 
-            match recv_result
+            //  ジョブを受信
+            match self.queue.pop(index, timeout)
             {
-                Ok(f) =>
+                Pop::Job(f) =>
                 {
-                    //  スレッドが停止していた場合のための再起動処理
-                    let _ignored = self.start_threads();
-
                     //  ジョブを実行
-                    f();
+                    //  パニックはワーカーの内部で捕捉して通知する
+                    self.set_busy(index, true);
+                    let started = Instant::now();
+                    let result = catch_unwind(AssertUnwindSafe(f));
+                    self.add_busy_time(index, started.elapsed());
+                    self.set_busy(index, false);
+                    self.job_finished(result);
                 },
 
-                //  タイムアウトの場合は何もしない
-                Err(RecvTimeoutError::Timeout) => {},
+                //  アイドル状態が続いた場合は最小スレッド数まで停止
+                Pop::Timeout =>
+                {
+                    if self.try_retire(self.min_size())
+                    {
+                        worker.retired = true;
+                        return;
+                    }
+                },
 
-                //  チャネルに接続できなかった場合はスレッドを停止
-                Err(RecvTimeoutError::Disconnected) => return,
+                //  ジョブがないまま起床された場合は何もしない
+                Pop::Notified => {},
+
+                //  キューが閉じられた場合はスレッドを停止
+                Pop::Closed => return,
             }
+        }
+    }
 
-            //  スレッドが停止していた場合のための再起動処理
-            let _ignored = self.start_threads();
+    //--------------------------------------------------------------------------
+    //  ジョブの完了かパニックを記録
+    //--------------------------------------------------------------------------
+    fn job_finished( &self, result: std::thread::Result<()> )
+    {
+        match result
+        {
+            Ok(()) => { self.num_completed.next(); },
+            Err(payload) => self.job_panicked(payload),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドから待機せずにキューのジョブを1つ実行
+    //  ジョブがなかった場合は `false` を返す
+    //
+    //  ジョブの内部から呼び出されるので、実行中の状態と実行時間は呼び出し元の
+    //  ジョブの分として記録される
+    //--------------------------------------------------------------------------
+    pub(crate) fn run_pending_job( &self ) -> bool
+    {
+        match self.queue.try_pop()
+        {
+            Some(job) =>
+            {
+                self.job_finished(catch_unwind(AssertUnwindSafe(job)));
+                true
+            },
+            None => false,
         }
     }
 
+    //--------------------------------------------------------------------------
+    //  ワーカーがジョブを実行中かどうかを記録
+    //--------------------------------------------------------------------------
+    fn set_busy( &self, index: usize, busy: bool )
+    {
+        self.slots.read().unwrap()[index].busy.store(busy, Ordering::Release);
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカーの累積の実行時間に加算
+    //--------------------------------------------------------------------------
+    fn add_busy_time( &self, index: usize, elapsed: Duration )
+    {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.slots.read().unwrap()[index].busy_nanos.fetch_add(nanos, Ordering::AcqRel);
+    }
+
     //--------------------------------------------------------------------------
     //  単一のスレッドを生成
     //--------------------------------------------------------------------------
-    fn spawn_thread(
-        &self,
-        num_live_threads: usize,
-        name: String,
-        f: impl FnOnce() + Send + 'static,
-    ) -> Result<(), std::io::Error>
+    fn spawn_thread( &self, name: String, f: impl FnOnce() + Send + 'static )
+        -> Result<(), std::io::Error>
+    {
+        let mut builder = std::thread::Builder::new().name(name);
+        if let Some(stack_size) = self.stack_size
+        {
+            builder = builder.stack_size(stack_size);
+        }
+        builder.spawn(f)?;
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数の上限と予算を確認
+    //--------------------------------------------------------------------------
+    fn acquire_thread( &self, num_live_threads: usize ) -> Result<(), StartThreadsError>
     {
         //  起動中のスレッド数が上限に達した場合はエラー
-        if num_live_threads >= INTERNAL_MAX_THREADS.load(std::sync::atomic::Ordering::Acquire)
+        if num_live_threads >= self.thread_limit
         {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Number of threads reached the upper limit".to_string()
-            ));
+            return Err(StartThreadsError::LimitReached(self.thread_limit));
         }
 
-        //  スレッドを生成
-        std::thread::Builder::new().name(name).spawn(f)?;
-        Ok(())
+        //  共有する予算が足りない場合はエラー
+        match &self.budget
+        {
+            Some(budget) if !budget.try_acquire() =>
+            {
+                Err(StartThreadsError::LimitReached(budget.limit()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  他のプールと共有する予算が返却されるまで待つ
+    //  予算を共有していないか、プール自身の上限に達している場合は待たずに
+    //  `false` を返す
+    //--------------------------------------------------------------------------
+    pub(crate) fn wait_budget( &self ) -> bool
+    {
+        match &self.budget
+        {
+            Some(budget) if self.num_live_threads() < self.thread_limit =>
+            {
+                budget.wait_available();
+                true
+            },
+            _ => false,
+        }
     }
 
     //--------------------------------------------------------------------------
     //  単一のスレッドを起動
+    //  上限に達しているか、空いているスロットがなければ起動せずに `false` を返す
     //--------------------------------------------------------------------------
-    fn start_thread( self: &Arc<Self> ) -> Result<(), StartThreadsError>
+    fn start_thread( self: &Arc<Self>, limit: usize ) -> Result<bool, StartThreadsError>
     {
-        let self_clone = self.clone();
+        let index = match self.claim_slot(limit)
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+
         let num_live_threads = self.num_live_threads() - 1;
+        if let Err(e) = self.acquire_thread(num_live_threads)
+        {
+            self.num_live.fetch_sub(1, Ordering::AcqRel);
+            self.release_slot(index);
+            return Err(e);
+        }
 
-        //  起動中のスレッド数が目的のスレッド数よりも小さければ、スレッドを生成
-        if num_live_threads < self.size
+        let self_clone = self.clone();
+        let name_num = self.next_name_num.next();
+        let name = match &self.thread_name
         {
-            if let Err(e) = self.spawn_thread(
-                num_live_threads,
-                format!("{}-{}", self.name, self.next_name_num.next()),
-                move || self_clone.work()
-            )
+            Some(thread_name) => thread_name(name_num),
+            None => format!("{}-{}", self.name, name_num),
+        };
+        let cpu = self.cpu_affinity.as_ref().and_then(|affinity| affinity.cpu_for_slot(index));
+        if let Err(e) = self.spawn_thread(name, move || self_clone.work(index, cpu))
+        {
+            //  スレッドの起動に失敗した場合はエラー
+            if let Some(budget) = &self.budget
             {
-                //  スレッドの起動に失敗した場合はエラー
+                budget.release();
+            }
+            self.num_live.fetch_sub(1, Ordering::AcqRel);
+            self.release_slot(index);
 
-                if num_live_threads == 0
-                {
-                    return Err(StartThreadsError::NoThreads(e));
-                }
-                else
-                {
-                    return Err(StartThreadsError::Respawn(e));
-                }
-            };
-        }
+            let degraded = self.spawn_failed(&e);
+            if num_live_threads == 0 && degraded
+            {
+                return Err(StartThreadsError::PoolDegraded(e));
+            }
+            else if num_live_threads == 0
+            {
+                return Err(StartThreadsError::NoThreads(e));
+            }
+            else
+            {
+                return Err(StartThreadsError::Respawn(e));
+            }
+        };
 
-        Ok(())
+        //  起動に成功した場合は劣化状態を解除
+        self.num_spawn_failures.store(0, Ordering::Release);
+        self.degraded.store(false, Ordering::Release);
+        Ok(true)
     }
 
     //--------------------------------------------------------------------------
@@ -143,10 +720,33 @@ impl Inner
     //--------------------------------------------------------------------------
     pub(crate) fn start_threads( self: &Arc<Self> ) -> Result<(), StartThreadsError>
     {
-        //  プールサイズに達するまでスレッドを起動
-        while self.num_live_threads() < self.size
+        //  最小スレッド数に達するまでスレッドを起動
+        //  キューが閉じられた後は起動しない
+        //  停止中のワーカーがまだスロットを解放していない場合は、次のスケジュ
+        //  ール時に起動する
+        while self.num_live_threads() < self.min_size() && !self.queue.is_closed()
+        {
+            if !self.start_thread(self.min_size())?
+            {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューにジョブが溜まっていれば、最大スレッド数までスレッドを追加
+    //  起床中のワーカーも待機中として数えられるので、待機中のワーカーより多い
+    //  ジョブが溜まっている場合に追加する
+    //--------------------------------------------------------------------------
+    pub(crate) fn grow_if_backlogged( self: &Arc<Self> ) -> Result<(), StartThreadsError>
+    {
+        if self.queue.len() > self.queue.num_sleepers()
+            && self.num_live_threads() < self.max_size()
+            && !self.queue.is_closed()
         {
-            self.start_thread()?;
+            self.start_thread(self.max_size())?;
         }
 
         Ok(())
@@ -0,0 +1,117 @@
+/*
+
+    ジョブの結果を受け取るハンドル
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `ThreadPool::spawn()` でスケジュールしたジョブの戻り値を受け取る。
+
+    - `join()` でジョブの完了までスレッドをブロックする
+    - `join_timeout()` でタイムアウトを上限としてジョブの完了を待つ
+    - `.await` で非同期にジョブの完了を待つ
+
+    ジョブがパニックになった場合は、ペイロードを含む `JoinError::Panic` が返る。
+    ハンドルをドロップしてもジョブはキャンセルされない。
+
+*/
+
+use crate::error::JoinError;
+
+use core::any::Any;
+use core::fmt::{ Debug, Formatter };
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+use core::time::Duration;
+use std::any::type_name;
+use std::panic::{ catch_unwind, AssertUnwindSafe };
+use std::sync::mpsc::RecvTimeoutError;
+use fezer_sync::channel::{ oneshot, OneSender, Receiver };
+
+//  ジョブの実行結果
+type JobResult<T> = Result<T, Box<dyn Any + Send + 'static>>;
+
+//------------------------------------------------------------------------------
+//  結果をハンドルに送信するジョブを生成
+//------------------------------------------------------------------------------
+pub(crate) fn job_with_handle<T, F>( f: F ) -> (impl FnOnce() + Send + 'static, JoinHandle<T>)
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver): (OneSender<JobResult<T>>, _) = oneshot();
+    let job = move ||
+    {
+        //  ハンドルが既にドロップされていた場合は結果を破棄
+        let _ignored = sender.send(catch_unwind(AssertUnwindSafe(f)));
+    };
+    (job, JoinHandle { receiver })
+}
+
+//------------------------------------------------------------------------------
+//  JoinHandle
+//------------------------------------------------------------------------------
+pub struct JoinHandle<T: Send>
+{
+    receiver: Receiver<JobResult<T>>,
+}
+
+impl<T: Send> JoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  ジョブが完了するまで待つ
+    //--------------------------------------------------------------------------
+    pub fn join( self ) -> Result<T, JoinError>
+    {
+        match self.receiver.recv()
+        {
+            Ok(result) => result.map_err(JoinError::Panic),
+            Err(_) => Err(JoinError::Dropped),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タイムアウトを上限としてジョブの完了を待つ
+    //  タイムアウトした場合も、ハンドルを用いて再度待つことができる
+    //--------------------------------------------------------------------------
+    pub fn join_timeout( &self, timeout: Duration ) -> Result<T, JoinError>
+    {
+        match self.receiver.recv_timeout(timeout)
+        {
+            Ok(result) => result.map_err(JoinError::Panic),
+            Err(RecvTimeoutError::Timeout) => Err(JoinError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(JoinError::Dropped),
+        }
+    }
+}
+
+impl<T: Send> Future for JoinHandle<T>
+{
+    type Output = Result<T, JoinError>;
+
+    //--------------------------------------------------------------------------
+    //  poll
+    //--------------------------------------------------------------------------
+    fn poll( mut self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
+    {
+        match Pin::new(&mut self.receiver).poll(cx)
+        {
+            Poll::Ready(Ok(result)) => Poll::Ready(result.map_err(JoinError::Panic)),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Dropped)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: Send> Debug for JoinHandle<T>
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> core::fmt::Result
+    {
+        write!(f, "JoinHandle<{}>", type_name::<T>())
+    }
+}
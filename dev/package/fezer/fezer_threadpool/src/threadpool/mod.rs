@@ -8,24 +8,56 @@
 
     スレッドのコレクションと、実行するジョブのキュー。
 
-    スレッドはパニックになるジョブを実行すると停止し、1つ以上のスレッドが残って
-    いれば自動的にスレッドが再起動される。また、新しいジョブをスケジュールしたと
-    きにも起動中のスレッド数のチェックと不足分のスレッドの再起動が実行される。
+    ジョブのキューはワークスティーリング方式で、プールの外部からスケジュールさ
+    れたジョブはグローバルなインジェクタに、ワーカースレッドの内部からスケジュ
+    ールされたジョブはそのワーカーのローカルキューに追加される。アイドル状態の
+    ワーカーは他のワーカーのローカルキューからジョブを盗む。
+
+    ジョブのパニックはワーカースレッドの内部で捕捉され、パニックハンドラに通知
+    される。スレッド自体がパニックで停止した場合は、監視スレッドが自動的にスレ
+    ッドを再起動する。また、新しいジョブをスケジュールしたときにも起動中のスレ
+    ッド数のチェックと不足分のスレッドの再起動が実行される。
 
 */
 
+mod affinity;
+mod batch;
+mod budget;
+mod builder;
+mod cancel;
 mod inner;
+mod join_handle;
+mod par_iter;
+mod priority;
+mod queue;
+mod schedule_fut;
+mod scope;
+mod shutdown;
+mod stats;
+mod supervisor;
+mod timer;
+
+pub use affinity::CpuAffinity;
+pub use budget::ThreadBudget;
+pub use builder::ThreadPoolBuilder;
+pub use cancel::{ CancelHandle, CancellationToken };
+pub use join_handle::JoinHandle;
+pub use priority::Priority;
+pub use schedule_fut::ScheduleFut;
+pub use scope::Scope;
+pub use shutdown::{ ShutdownMode, ShutdownReport };
+pub use stats::{ ThreadPoolStats, WorkerStats };
+pub use timer::{ Periodic, TimerHandle };
 
-use crate::atomic_counter::AtomicCounter;
-use crate::error::{ NewThreadPoolError, StartThreadsError, TryScheduleError };
+use crate::error::{ NewThreadPoolError, ShutdownError, StartThreadsError, TryScheduleError };
 use crate::threadpool::inner::Inner;
+use crate::threadpool::join_handle::job_with_handle;
+use crate::threadpool::queue::{ PushError, ReserveError };
 
 use core::fmt::{ Debug, Formatter };
 use core::time::Duration;
-use std::sync::mpsc::{ sync_channel, SyncSender, TrySendError };
-use std::sync::{ Arc, Mutex };
+use std::sync::Arc;
 use std::convert::Into;
-use std::time::Instant;
 
 //------------------------------------------------------------------------------
 //  スレッドのスリープ
@@ -41,10 +73,7 @@ fn sleep_ms( ms: u64 )
 pub struct ThreadPool
 {
     //  スレッドのコレクション
-    inner: Arc<Inner>,
-
-    //  ジョブのSender
-    sender: SyncSender<Box<dyn FnOnce() + Send>>,
+    pub(crate) inner: Arc<Inner>,
 }
 
 impl ThreadPool
@@ -79,32 +108,175 @@ impl ThreadPool
             )
         }
 
-        let (sender, receiver) = sync_channel(size * 200);
-        let inner = Inner
+        ThreadPoolBuilder::new(name).size(size).build()
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッド数が伸縮する新しいスレッドプールを生成
+    //
+    //  キューにジョブが溜まると `max_size` までスレッドを追加し、`keep_alive`
+    //  の間ジョブを受信しなかったスレッドは `min_size` まで停止する
+    //--------------------------------------------------------------------------
+    pub fn new_elastic(
+        name: &'static str,
+        min_size: usize,
+        max_size: usize,
+        keep_alive: Duration,
+    ) -> Result<Self, NewThreadPoolError>
+    {
+        //  名前が指定されていなかった場合
+        if name.is_empty()
         {
-            name,
-            next_name_num: AtomicCounter::new(),
-            size,
-            receiver: Mutex::new(receiver),
-        };
-        let pool = Self
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    "ThreadPool::new_elastic called with empty name".to_string()
+                )
+            )
+        }
+
+        //  スレッド数の範囲が不正だった場合
+        if max_size < 1 || min_size > max_size
         {
-            inner: Arc::new(inner),
-            sender,
-        };
+            return Err
+            (
+                NewThreadPoolError::Parameter
+                (
+                    format!
+                    (
+                        "ThreadPool::new_elastic called with invalid size range: {:?}..={:?}",
+                        min_size,
+                        max_size
+                    )
+                )
+            )
+        }
 
-        //  スレッドの起動
-        pool.inner.start_threads()?;
+        ThreadPoolBuilder::new(name)
+            .size_range(min_size, max_size)
+            .keep_alive(keep_alive)
+            .build()
+    }
 
-        Ok(pool)
+    //--------------------------------------------------------------------------
+    //  スレッドプールのビルダを生成
+    //--------------------------------------------------------------------------
+    pub fn builder( name: impl Into<String> ) -> ThreadPoolBuilder
+    {
+        ThreadPoolBuilder::new(name)
     }
 
     //--------------------------------------------------------------------------
     //  プールのスレッド数を取得
+    //  伸縮するプールの場合は最大スレッド数
     //--------------------------------------------------------------------------
     pub fn size( &self ) -> usize
     {
-        self.inner.size
+        self.inner.max_size()
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールの最小スレッド数を取得
+    //--------------------------------------------------------------------------
+    pub fn min_size( &self ) -> usize
+    {
+        self.inner.min_size()
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールで起動できるスレッド数の上限を取得
+    //--------------------------------------------------------------------------
+    pub fn thread_limit( &self ) -> usize
+    {
+        self.inner.thread_limit()
+    }
+
+    //--------------------------------------------------------------------------
+    //  パニックになったジョブの数を取得
+    //  `spawn()` と `scope()` のジョブのパニックは呼び出し元に返されるので含ま
+    //  ない
+    //--------------------------------------------------------------------------
+    pub fn num_panicked_jobs( &self ) -> usize
+    {
+        self.inner.num_panicked.get()
+    }
+
+    //--------------------------------------------------------------------------
+    //  パニックによってプールが停止されたかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_aborted( &self ) -> bool
+    {
+        self.inner.is_aborted()
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドの起動に続けて失敗して劣化状態になっているかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_degraded( &self ) -> bool
+    {
+        self.inner.is_degraded()
+    }
+
+    //--------------------------------------------------------------------------
+    //  アイドル状態のスレッドを停止するまでの時間を取得
+    //--------------------------------------------------------------------------
+    pub fn keep_alive( &self ) -> Duration
+    {
+        self.inner.keep_alive()
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールのスレッド数を変更
+    //
+    //  スレッド数が増えた場合はすぐにスレッドを起動し、減った場合は超過したス
+    //  レッドが実行中のジョブの完了後に停止する
+    //--------------------------------------------------------------------------
+    pub fn set_size( &self, size: usize ) -> Result<(), StartThreadsError>
+    {
+        //  スレッド数の指定が0以下だった場合
+        if size < 1
+        {
+            return Err
+            (
+                StartThreadsError::Parameter
+                (
+                    format!
+                    (
+                        "ThreadPool::set_size called with invalid size value: {:?}",
+                        size
+                    )
+                )
+            )
+        }
+
+        self.inner.set_size_range(size, size)
+    }
+
+    //--------------------------------------------------------------------------
+    //  プールのスレッド数の範囲を変更して、伸縮するプールにする
+    //--------------------------------------------------------------------------
+    pub fn set_size_range( &self, min_size: usize, max_size: usize )
+        -> Result<(), StartThreadsError>
+    {
+        //  スレッド数の範囲が不正だった場合
+        if max_size < 1 || min_size > max_size
+        {
+            return Err
+            (
+                StartThreadsError::Parameter
+                (
+                    format!
+                    (
+                        "ThreadPool::set_size_range called with invalid size range: {:?}..={:?}",
+                        min_size,
+                        max_size
+                    )
+                )
+            )
+        }
+
+        self.inner.set_size_range(min_size, max_size)
     }
 
     //--------------------------------------------------------------------------
@@ -117,8 +289,19 @@ impl ThreadPool
 
     //--------------------------------------------------------------------------
     //  ジョブをスケジュール
+    //
+    //  プールが劣化状態でスレッドがない場合は、スレッドの起動を待たずにジョブを
+    //  キューに追加して戻る
     //--------------------------------------------------------------------------
     pub fn schedule<F: FnOnce() + Send + 'static>( &self, f: F )
+    {
+        self.schedule_with_priority(Priority::Normal, f);
+    }
+
+    //--------------------------------------------------------------------------
+    //  優先度を指定してジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_with_priority<F: FnOnce() + Send + 'static>( &self, priority: Priority, f: F )
     {
         let mut opt_box_f: Option<Box<dyn FnOnce() + Send + 'static>> = Some(Box::new(f));
 
@@ -127,7 +310,24 @@ impl ThreadPool
             //  スレッドの再起動処理
             match self.inner.start_threads()
             {
-                Ok(()) | Err(StartThreadsError::Respawn(_)) => {},
+                Ok(())
+                | Err(StartThreadsError::Respawn(_))
+                | Err(StartThreadsError::Parameter(_)) => {},
+                Err(StartThreadsError::LimitReached(_)) if self.num_live_threads() > 0 => {},
+
+                //  劣化状態ではジョブをキューに追加して、監視スレッドによる再起動
+                //  を待つ
+                Err(StartThreadsError::PoolDegraded(_)) => {},
+
+                //  他のプールが予算を使い切っている場合は、返却されるまで待って再
+                //  試行する。プール自身の上限に達している場合は待たずに追加する
+                Err(StartThreadsError::LimitReached(_)) =>
+                {
+                    if self.inner.wait_budget()
+                    {
+                        continue;
+                    }
+                },
                 Err(StartThreadsError::NoThreads(_)) =>
                 {
                     sleep_ms(10);
@@ -136,11 +336,17 @@ impl ThreadPool
             }
 
             //  キューにジョブを送信
-            opt_box_f = match self.sender.try_send(opt_box_f.take().unwrap())
+            opt_box_f = match self.inner.queue.push(opt_box_f.take().unwrap(), priority)
             {
-                Ok(()) => return,
-                Err(TrySendError::Disconnected(_)) => unreachable!(),
-                Err(TrySendError::Full(box_f)) => Some(box_f),
+                Ok(()) =>
+                {
+                    //  ジョブが溜まっている場合はスレッドを追加
+                    let _ignored = self.inner.grow_if_backlogged();
+                    return;
+                },
+                //  パニックで停止したプールではジョブを破棄
+                Err(PushError::Closed(_)) => return,
+                Err(PushError::Full(box_f)) => Some(box_f),
             };
 
             //  キューがいっぱいだった場合はスリープしてから再試行
@@ -148,19 +354,60 @@ impl ThreadPool
         }
     }
 
+    //--------------------------------------------------------------------------
+    //  ジョブをスケジュールして、結果を受け取るハンドルを返す
+    //--------------------------------------------------------------------------
+    pub fn spawn<T, F>( &self, f: F ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.spawn_with_priority(Priority::Normal, f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  優先度を指定してジョブをスケジュールして、結果を受け取るハンドルを返す
+    //--------------------------------------------------------------------------
+    pub fn spawn_with_priority<T, F>( &self, priority: Priority, f: F ) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (job, handle) = job_with_handle(f);
+        self.schedule_with_priority(priority, job);
+        handle
+    }
+
     //--------------------------------------------------------------------------
     //  ジョブをスケジュール（再試行なし）
+    //  キューに追加できなかった場合はエラーと一緒にジョブを返す
+    //--------------------------------------------------------------------------
+    pub fn try_schedule<F>( &self, f: F ) -> Result<(), TryScheduleError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_schedule_with_priority(Priority::Normal, f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  優先度を指定してジョブをスケジュール（再試行なし）
     //--------------------------------------------------------------------------
-    pub fn try_schedule( &self, f: impl FnOnce() + Send + 'static ) -> Result<(), TryScheduleError>
+    pub fn try_schedule_with_priority<F>( &self, priority: Priority, f: F )
+        -> Result<(), TryScheduleError<F>>
+    where
+        F: FnOnce() + Send + 'static,
     {
         //  キューにジョブを送信
-        match self.sender.try_send(Box::new(f))
+        match self.inner.queue.reserve()
         {
-            Ok(_) => {},
-            Err(TrySendError::Disconnected(_)) => unreachable!(),
-            Err(TrySendError::Full(_)) => return Err(TryScheduleError::QueueFull),
+            Ok(()) => self.inner.queue.push_reserved(Box::new(f), priority),
+            Err(ReserveError::Closed) => return Err(TryScheduleError::Aborted(f)),
+            Err(ReserveError::Full) => return Err(TryScheduleError::QueueFull(f)),
         }
-        self.inner.start_threads().map_err(Into::into)
+        self.inner
+            .start_threads()
+            .and_then(|()| self.inner.grow_if_backlogged())
+            .map_err(Into::into)
     }
 
     //--------------------------------------------------------------------------
@@ -168,35 +415,28 @@ impl ThreadPool
     //--------------------------------------------------------------------------
     pub fn join( self )
     {
-        let inner = self.inner.clone();
-        drop(self);
-        while inner.num_live_threads() > 0
-        {
-            sleep_ms(10);
-        }
+        self.shutdown(ShutdownMode::Drain);
     }
 
     //--------------------------------------------------------------------------
     //  ThreadPoolをドロップ
     //  タイムアウトを上限としてスレッドの停止を待つ
     //--------------------------------------------------------------------------
-    pub fn try_join( self, timeout: Duration ) -> Result<(), String>
+    pub fn try_join( self, timeout: Duration ) -> Result<(), ShutdownError>
     {
-        let inner = self.inner.clone();
-        drop(self);
-        let deadline = Instant::now() + timeout;
-        loop
-        {
-            if inner.num_live_threads() < 1
-            {
-                return Ok(());
-            }
-            if deadline < Instant::now()
-            {
-                return Err("timed out waiting for ThreadPool workers to stop".to_string());
-            }
-            sleep_ms(10);
-        }
+        self.shutdown_timeout(ShutdownMode::Drain, timeout).map(|_| ())
+    }
+}
+
+impl Drop for ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //  キューとタイマーを閉じて、ジョブがなくなったスレッドから順に停止させる
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        self.inner.close();
     }
 }
 
@@ -210,9 +450,389 @@ impl Debug for ThreadPool
         write!
         (
             f,
-            "ThreadPool{{{:?}, size={:?}}}",
+            "ThreadPool{{{:?}, size={:?}..={:?}}}",
             self.inner.name,
-            self.inner.size
+            self.inner.min_size(),
+            self.inner.max_size()
         )
     }
 }
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::ThreadPool;
+    use crate::error::{ JoinError, NewThreadPoolError, StartThreadsError, TryScheduleError };
+    use core::future::Future;
+    use core::time::Duration;
+    use std::collections::HashSet;
+    use std::sync::{ Arc, Barrier };
+    use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+
+    //--------------------------------------------------------------------------
+    //  test_schedule
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let receiver =
+        {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            for n in 0..1000
+            {
+                let sender_clone = sender.clone();
+                pool.schedule(move || sender_clone.send(n).unwrap());
+            }
+            receiver
+        };
+        let mut values: Vec<usize> = receiver.iter().collect();
+        values.sort();
+        assert_eq!((0_usize..1000).collect::<Vec<usize>>(), values);
+        pool.join();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_steal_from_local_queue
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_steal_from_local_queue()
+    {
+        let pool = Arc::new(ThreadPool::new("test", 4).unwrap());
+        let barrier = Arc::new(Barrier::new(4));
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        //  ワーカーの内部から、他のワーカーが揃うまで終わらないジョブを生成
+        let pool_clone = pool.clone();
+        pool.schedule(move ||
+        {
+            for _ in 0..4
+            {
+                let barrier_clone = barrier.clone();
+                let sender_clone = sender.clone();
+                pool_clone.schedule(move ||
+                {
+                    barrier_clone.wait();
+                    sender_clone.send(std::thread::current().name().unwrap().to_string()).unwrap();
+                });
+            }
+        });
+
+        let names: HashSet<String> = (0..4)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert_eq!(4, names.len());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_join
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_join()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        let handles: Vec<_> = (0..10).map(|n| pool.spawn(move || n * 2)).collect();
+        let results: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!((0..10).map(|n| n * 2).collect::<Vec<usize>>(), results);
+
+        let handle = pool.spawn(||
+        {
+            std::thread::sleep(Duration::from_millis(200));
+            1
+        });
+        assert_eq!(JoinError::Timeout, handle.join_timeout(Duration::from_millis(1)).unwrap_err());
+        assert_eq!(1, handle.join_timeout(Duration::from_secs(5)).unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_await
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_await()
+    {
+        //  現在のスレッドをunparkするWaker
+        struct ThreadWaker(std::thread::Thread);
+        impl std::task::Wake for ThreadWaker
+        {
+            fn wake( self: Arc<Self> )
+            {
+                self.0.unpark();
+            }
+        }
+
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let mut handle = pool.spawn(||
+        {
+            std::thread::sleep(Duration::from_millis(50));
+            5
+        });
+
+        let waker = std::task::Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut context = std::task::Context::from_waker(&waker);
+        let result = loop
+        {
+            match std::pin::Pin::new(&mut handle).poll(&mut context)
+            {
+                std::task::Poll::Ready(result) => break result,
+                std::task::Poll::Pending => std::thread::park(),
+            }
+        };
+        assert_eq!(5, result.unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_spawn_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_spawn_panic()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let err = pool.spawn(|| -> usize { panic!("job panicked") }).join().unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!("ThreadPool job panicked: job panicked", err.to_string());
+
+        //  ジョブのパニックでワーカーは停止しない
+        assert_eq!(3, pool.spawn(|| 3).join().unwrap());
+        assert_eq!(1, pool.num_live_threads());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_new_invalid
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_new_invalid()
+    {
+        //  名前はスレッド数より先に検証される
+        assert_eq!
+        (
+            NewThreadPoolError::Parameter("ThreadPool::new called with empty name".to_string()),
+            ThreadPool::new("", 0).unwrap_err()
+        );
+        assert_eq!
+        (
+            NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new called with invalid size value: 0".to_string()
+            ),
+            ThreadPool::new("test", 0).unwrap_err()
+        );
+        assert_eq!
+        (
+            NewThreadPoolError::Parameter
+            (
+                "ThreadPool::new_elastic called with invalid size range: 2..=1".to_string()
+            ),
+            ThreadPool::new_elastic("test", 2, 1, Duration::from_millis(50)).unwrap_err()
+        );
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_set_size
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_set_size()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        assert_eq!(2, pool.num_live_threads());
+
+        pool.set_size(6).unwrap();
+        assert_eq!(6, pool.size());
+        assert_eq!(6, pool.num_live_threads());
+
+        pool.set_size(1).unwrap();
+        wait_for(|| pool.num_live_threads() == 1);
+        assert_eq!(7, pool.spawn(|| 7).join().unwrap());
+
+        //  不正なスレッド数はエラーになり、スレッド数は変わらない
+        assert_eq!
+        (
+            Err(StartThreadsError::Parameter
+            (
+                "ThreadPool::set_size called with invalid size value: 0".to_string()
+            )),
+            pool.set_size(0)
+        );
+        assert_eq!
+        (
+            Err(StartThreadsError::Parameter
+            (
+                "ThreadPool::set_size_range called with invalid size range: 3..=2".to_string()
+            )),
+            pool.set_size_range(3, 2)
+        );
+        assert_eq!(1, pool.size());
+
+        //  `TryScheduleError` に変換してもメッセージが保たれる
+        let err: TryScheduleError<()> = pool.set_size(0).unwrap_err().into();
+        assert_eq!("ThreadPool::set_size called with invalid size value: 0", err.to_string());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_elastic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_elastic()
+    {
+        let pool = ThreadPool::new_elastic("test", 1, 4, Duration::from_millis(50)).unwrap();
+        assert_eq!(1, pool.num_live_threads());
+
+        //  ジョブが溜まるとスレッドが追加される
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4
+        {
+            let barrier_clone = barrier.clone();
+            pool.schedule(move || { barrier_clone.wait(); });
+        }
+        barrier.wait();
+        assert_eq!(4, pool.num_live_threads());
+
+        //  アイドル状態が続くと最小スレッド数まで停止する
+        wait_for(|| pool.num_live_threads() == 1);
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_elastic_with_sleepers
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_elastic_with_sleepers()
+    {
+        let pool = ThreadPool::new_elastic("test", 1, 4, Duration::from_secs(10)).unwrap();
+        wait_for(|| pool.inner.queue.num_sleepers() == 1);
+
+        //  待機中のワーカーが起床する前にジョブが溜まっても、スレッドが追加さ
+        //  れて全てのジョブが同時に実行される
+        let num_started = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+        for _ in 0..4
+        {
+            let num_started = num_started.clone();
+            let release = release.clone();
+            pool.schedule(move ||
+            {
+                num_started.fetch_add(1, Ordering::SeqCst);
+                while !release.load(Ordering::SeqCst)
+                {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            });
+        }
+        wait_for(|| num_started.load(Ordering::SeqCst) == 4);
+        release.store(true, Ordering::SeqCst);
+        assert_eq!(4, pool.num_live_threads());
+    }
+
+    //--------------------------------------------------------------------------
+    //  条件が満たされるまで待つ
+    //--------------------------------------------------------------------------
+    fn wait_for( f: impl Fn() -> bool )
+    {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !f()
+        {
+            assert!(std::time::Instant::now() < deadline, "timed out waiting for condition");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_respawn_after_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_respawn_after_panic()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        for _ in 0..4
+        {
+            pool.schedule(|| panic!("job panicked"));
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule(move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(2, pool.num_live_threads());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_respawn_after_handler_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_respawn_after_handler_panic()
+    {
+        //  パニックハンドラ自体のパニックはワーカーを停止させる
+        let pool = ThreadPool::builder("test")
+            .size(2)
+            .panic_handler(|_, _| panic!("panic handler panicked"))
+            .build()
+            .unwrap();
+        for _ in 0..4
+        {
+            pool.schedule(|| panic!("job panicked"));
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule(move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        wait_for(|| pool.stats().num_respawned == 4);
+        wait_for(|| pool.num_live_threads() == 2);
+        assert_eq!(4, pool.num_panicked_jobs());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_panic_handler
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_panic_handler()
+    {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        let pool = ThreadPool::builder("test")
+            .size(2)
+            .thread_name(|n| format!("handler-{}", n))
+            .panic_handler(move |payload, worker_name|
+            {
+                let message = crate::error::panic_message(payload).unwrap_or_default().to_string();
+                sender.lock().unwrap().send((message, worker_name.to_string())).unwrap();
+            })
+            .build()
+            .unwrap();
+
+        pool.schedule(|| panic!("job {} panicked", 1));
+        let (message, worker_name) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!("job 1 panicked", message);
+        assert!(worker_name.starts_with("handler-"));
+
+        //  ジョブのパニックでワーカーは停止しない
+        assert_eq!(1, pool.num_panicked_jobs());
+        assert_eq!(2, pool.num_live_threads());
+        assert!(!pool.is_aborted());
+        assert_eq!(Ok(()), pool.try_schedule(|| {}));
+        pool.join();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_abort_on_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_abort_on_panic()
+    {
+        let pool = ThreadPool::builder("test").size(1).abort_on_panic(true).build().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move ||
+        {
+            barrier_clone.wait();
+            panic!("job panicked");
+        });
+        let handle = pool.spawn(|| 1);
+        barrier.wait();
+
+        //  キューに残っていたジョブは破棄される
+        assert_eq!(Err(JoinError::Dropped), handle.join());
+        assert!(pool.is_aborted());
+        assert_eq!(1, pool.num_panicked_jobs());
+        assert!(matches!(pool.try_schedule(|| {}), Err(TryScheduleError::Aborted(_))));
+        wait_for(|| pool.num_live_threads() == 0);
+        pool.join();
+    }
+}
@@ -0,0 +1,189 @@
+/*
+
+    並列イテレータのヘルパ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    コレクションの要素をプールのワーカースレッドに分配して並列に処理する。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 4).unwrap();
+    let results: Vec<ProcessResult> = pool.par_map(data_source, |data| process_data(data));
+    pool.par_for_each(paths, |path| compress(path));
+    let sums: Vec<u64> = pool.par_chunks(&values, 1024, |chunk| chunk.iter().sum());
+    ```
+
+    - `par_map()` は入力の順序を保って結果を返す
+    - `par_for_each()` は結果を返さない
+    - `par_chunks()` はスライスを指定した大きさのチャンクに分け、チャンクごとの
+      結果を順に返す
+
+    `par_map()` と `par_for_each()` は、要素数とプールのスレッド数に合わせてチャ
+    ンクの大きさを決める。ワーカーごとに複数のチャンクを割り当てるので、要素ご
+    との処理時間に偏りがあってもワーカー間で負荷が分散される。
+
+    内部では `scope()` を用いるので、呼び出し元のスタックを借用できる。いずれか
+    の要素の処理がパニックになった場合は、すべてのチャンクの完了を待ってから呼
+    び出し元でパニックを再開する。
+
+*/
+
+use crate::threadpool::ThreadPool;
+
+//  ワーカーごとに割り当てるチャンクの数
+const CHUNKS_PER_WORKER: usize = 4;
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  要素数とスレッド数に合わせたチャンクの大きさ
+    //--------------------------------------------------------------------------
+    fn chunk_size( &self, len: usize ) -> usize
+    {
+        let num_chunks = self.size().saturating_mul(CHUNKS_PER_WORKER).max(1);
+        len.div_ceil(num_chunks).max(1)
+    }
+
+    //--------------------------------------------------------------------------
+    //  各要素にクロージャを並列に適用して、入力の順序で結果を返す
+    //--------------------------------------------------------------------------
+    pub fn par_map<I, F, R>( &self, iter: I, f: F ) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        let mut items: Vec<Option<I::Item>> = iter.into_iter().map(Some).collect();
+        let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+        let chunk_size = self.chunk_size(items.len());
+
+        let f = &f;
+        self.scope(|s|
+        {
+            for (item_chunk, result_chunk) in items
+                .chunks_mut(chunk_size)
+                .zip(results.chunks_mut(chunk_size))
+            {
+                s.spawn(move ||
+                {
+                    for (item, result) in item_chunk.iter_mut().zip(result_chunk.iter_mut())
+                    {
+                        *result = item.take().map(f);
+                    }
+                });
+            }
+        });
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    //--------------------------------------------------------------------------
+    //  各要素にクロージャを並列に適用
+    //--------------------------------------------------------------------------
+    pub fn par_for_each<I, F>( &self, iter: I, f: F )
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) + Sync,
+    {
+        let mut items: Vec<Option<I::Item>> = iter.into_iter().map(Some).collect();
+        let chunk_size = self.chunk_size(items.len());
+
+        let f = &f;
+        self.scope(|s|
+        {
+            for item_chunk in items.chunks_mut(chunk_size)
+            {
+                s.spawn(move ||
+                {
+                    item_chunk.iter_mut().filter_map(Option::take).for_each(f);
+                });
+            }
+        });
+    }
+
+    //--------------------------------------------------------------------------
+    //  スライスを指定した大きさのチャンクに分けて並列に処理し、チャンクの順序で
+    //  結果を返す
+    //
+    //  ※ チャンクの大きさが0の場合はpanic
+    //--------------------------------------------------------------------------
+    pub fn par_chunks<T, F, R>( &self, slice: &[T], chunk_size: usize, f: F ) -> Vec<R>
+    where
+        T: Sync,
+        F: Fn(&[T]) -> R + Sync,
+        R: Send,
+    {
+        assert!(chunk_size > 0, "chunk_size must be greater than zero");
+
+        let chunks: Vec<&[T]> = slice.chunks(chunk_size).collect();
+        self.par_map(chunks, f)
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::threadpool::ThreadPool;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    //--------------------------------------------------------------------------
+    //  test_par_map
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_par_map()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let offset = 10;
+        let results = pool.par_map(0..1000, |n| n * 2 + offset);
+        assert_eq!((0..1000).map(|n| n * 2 + offset).collect::<Vec<usize>>(), results);
+        assert!(pool.par_map(Vec::<usize>::new(), |n| n).is_empty());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_par_for_each
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_par_for_each()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let sum = AtomicUsize::new(0);
+        pool.par_for_each(1..=100, |n| { sum.fetch_add(n, Ordering::AcqRel); });
+        assert_eq!(5050, sum.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_par_chunks
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_par_chunks()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let values: Vec<usize> = (1..=10).collect();
+        let sums = pool.par_chunks(&values, 3, |chunk| chunk.iter().sum::<usize>());
+        assert_eq!(vec![6, 15, 24, 10], sums);
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_par_map_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_par_map_panic()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+        {
+            pool.par_map(0..100, |n| if n == 42 { panic!("item panicked") } else { n })
+        }));
+        assert_eq!(&"item panicked", result.unwrap_err().downcast_ref::<&str>().unwrap());
+
+        //  パニックの後もプールは利用できる
+        assert_eq!(vec![1, 2, 3], pool.par_map(vec![0, 1, 2], |n| n + 1));
+    }
+}
@@ -0,0 +1,119 @@
+/*
+
+    ジョブの優先度
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    ジョブは `High`、`Normal`、`Low` の3段階の優先度でスケジュールできる。優先
+    度を指定しない `schedule()` や `spawn()` は `Normal` になる。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 4).unwrap();
+    pool.schedule_with_priority(Priority::Low, || compact_database());
+    let handle = pool.spawn_with_priority(Priority::High, || handle_request());
+    ```
+
+    ワーカーは常にジョブが残っている最も高い優先度のジョブを実行する。ただし、
+    低い優先度のジョブが実行されないままにならないように、一定の間隔で低い優先
+    度のジョブを先に実行する。
+
+*/
+
+//  優先度の段階数
+pub(crate) const NUM_PRIORITIES: usize = 3;
+
+//------------------------------------------------------------------------------
+//  ジョブの優先度
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority
+{
+    //  レイテンシが重要なジョブ
+    High,
+
+    //  通常のジョブ
+    #[default]
+    Normal,
+
+    //  バックグラウンドで実行する大量のジョブ
+    Low,
+}
+
+impl Priority
+{
+    //--------------------------------------------------------------------------
+    //  キューのレーンのインデックス
+    //  優先度が高いほど小さい
+    //--------------------------------------------------------------------------
+    pub(crate) fn index( self ) -> usize
+    {
+        match self
+        {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::Priority;
+    use crate::threadpool::ThreadPool;
+    use std::sync::{ Arc, Barrier, Mutex };
+
+    //--------------------------------------------------------------------------
+    //  test_priority_order
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_priority_order()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move || { barrier_clone.wait(); });
+
+        //  ワーカーが停止している間にジョブを溜める
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for priority in [Priority::Low, Priority::Normal, Priority::High]
+        {
+            let order = order.clone();
+            pool.schedule_with_priority(priority, move || order.lock().unwrap().push(priority));
+        }
+        barrier.wait();
+        pool.join();
+        assert_eq!(vec![Priority::High, Priority::Normal, Priority::Low], *order.lock().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_priority_starvation
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_priority_starvation()
+    {
+        let pool = ThreadPool::builder("test").size(1).unbounded_queue().build().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move || { barrier_clone.wait(); });
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let order_clone = order.clone();
+        let low = pool.spawn_with_priority(Priority::Low, move || order_clone.lock().unwrap().len());
+        for n in 0..100
+        {
+            let order = order.clone();
+            pool.schedule_with_priority(Priority::High, move || order.lock().unwrap().push(n));
+        }
+        barrier.wait();
+
+        //  高い優先度のジョブがすべて終わる前に低い優先度のジョブが実行される
+        assert!(low.join().unwrap() < 100);
+        pool.join();
+    }
+}
@@ -0,0 +1,639 @@
+/*
+
+    ワークスティーリングのジョブキュー
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    グローバルなインジェクタと、ワーカースレッドごとのローカルキューからなるジ
+    ョブキュー。
+
+    - プールの外部からスケジュールされたジョブはインジェクタに追加される
+    - ワーカースレッドの内部からスケジュールされたジョブは、そのワーカーのロー
+      カルキューに追加される
+    - ワーカーはローカルキュー、インジェクタ、他のワーカーのローカルキューの順
+      にジョブを探す
+    - 他のワーカーからは、ローカルキューの後ろ半分をまとめて盗む
+
+    ジョブの総数は `capacity` を上限とし、ローカルキューのジョブも上限に含まれ
+    る。ローカルキューはプールのサイズの拡大に合わせて追加され、縮小しても削除
+    されない。
+
+    # 優先度
+
+    インジェクタとローカルキューは優先度ごとのレーンに分かれている。ワーカーは
+    ジョブが残っている最も高い優先度のレーンから、ローカルキュー、インジェクタ、
+    他のワーカーの順にジョブを探す。
+
+    高い優先度のジョブが途切れずに追加されても低い優先度のジョブが実行されるよ
+    うに、各ワーカーは `STARVATION_INTERVAL` 回に1回、最も低い優先度のレーンか
+    ら順にジョブを探す。
+
+    # アイドルワーカーの待機
+
+    ジョブが見つからないワーカーは `Condvar` で待機する。ジョブの追加側は待機中
+    のワーカーがいる場合にのみロックを獲得して通知するので、ワーカーが待機して
+    いない間はジョブの追加が単一のロックで直列化されることはない。
+
+    ワーカーはジョブの追加、キューを閉じたとき、スレッド数の変更のいずれかで起
+    床する。アイドル状態のワーカーを停止する必要がない間は、タイムアウトせずに
+    待機し続ける。他のワーカーのローカルキューのロックが競合してジョブを盗めな
+    かった場合は、`RETRY_BACKOFF` の間待機してから探し直す。
+
+    # 空きを待つ非同期の追加
+
+    キューが一杯のときに非同期にジョブを追加しようとしたタスクは、`Waker` を登
+    録して待つ。ジョブが取り出されるか、キューが閉じられると、登録されたすべて
+    の `Waker` が起床される。
+
+*/
+
+use crate::threadpool::priority::{ Priority, NUM_PRIORITIES };
+
+use core::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use core::time::Duration;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::{ Condvar, Mutex, RwLock };
+use std::task::Waker;
+
+//  キューに格納されるジョブ
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+//  優先度ごとのレーン
+type Lanes = [VecDeque<Job>; NUM_PRIORITIES];
+
+//  低い優先度のレーンを優先してジョブを探す間隔
+const STARVATION_INTERVAL: usize = 16;
+
+//  ジョブが残っているのに見つからなかったときに、探し直すまで待機する時間
+const RETRY_BACKOFF: Duration = Duration::from_millis(1);
+
+thread_local!
+{
+    //  現在のスレッドが属するキューのアドレスと、ワーカーのインデックス
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+
+    //  現在のスレッドがジョブを探した回数
+    static NUM_FINDS: Cell<usize> = const { Cell::new(0) };
+}
+
+//------------------------------------------------------------------------------
+//  ジョブの追加に失敗した理由
+//------------------------------------------------------------------------------
+pub(crate) enum PushError
+{
+    //  キューが一杯の場合
+    Full(Job),
+
+    //  キューが閉じられていた場合
+    Closed(Job),
+}
+
+//------------------------------------------------------------------------------
+//  ジョブ数の予約に失敗した理由
+//------------------------------------------------------------------------------
+pub(crate) enum ReserveError
+{
+    //  キューが一杯の場合
+    Full,
+
+    //  キューが閉じられていた場合
+    Closed,
+}
+
+//------------------------------------------------------------------------------
+//  ジョブの取得結果
+//------------------------------------------------------------------------------
+pub(crate) enum Pop
+{
+    //  ジョブを取得した
+    Job(Job),
+
+    //  タイムアウトまでにジョブがなかった
+    Timeout,
+
+    //  ジョブがないまま起床された
+    Notified,
+
+    //  キューが閉じられ、ジョブも残っていない
+    Closed,
+}
+
+//------------------------------------------------------------------------------
+//  JobQueue
+//------------------------------------------------------------------------------
+pub(crate) struct JobQueue
+{
+    //  プールの外部からスケジュールされたジョブ
+    injector: Mutex<Lanes>,
+
+    //  ワーカーごとのローカルキュー
+    locals: RwLock<Vec<Mutex<Lanes>>>,
+
+    //  キュー全体のジョブ数
+    len: AtomicUsize,
+
+    //  優先度ごとのジョブ数
+    lane_len: [AtomicUsize; NUM_PRIORITIES],
+
+    //  キュー全体のジョブ数の上限
+    capacity: usize,
+
+    //  キューが閉じられたかどうか
+    closed: AtomicBool,
+
+    //  待機中のワーカー数
+    sleepers: AtomicUsize,
+
+    //  ワーカーの待機用
+    park: Mutex<()>,
+    condvar: Condvar,
+
+    //  キューの空きを待っているタスクの `Waker`
+    sender_wakers: Mutex<Vec<Waker>>,
+
+    //  空きを待っているタスクの数
+    //  登録中のタスクも含む
+    num_sender_wakers: AtomicUsize,
+}
+
+impl JobQueue
+{
+    //--------------------------------------------------------------------------
+    //  新しいジョブキューを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new( num_workers: usize, capacity: usize ) -> Self
+    {
+        Self
+        {
+            injector: Mutex::new(Default::default()),
+            locals: RwLock::new((0..num_workers).map(|_| Mutex::new(Default::default())).collect()),
+            len: AtomicUsize::new(0),
+            lane_len: Default::default(),
+            capacity,
+            closed: AtomicBool::new(false),
+            sleepers: AtomicUsize::new(0),
+            park: Mutex::new(()),
+            condvar: Condvar::new(),
+            sender_wakers: Mutex::new(Vec::new()),
+            num_sender_wakers: AtomicUsize::new(0),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キュー全体のジョブ数
+    //--------------------------------------------------------------------------
+    pub(crate) fn len( &self ) -> usize
+    {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    //--------------------------------------------------------------------------
+    //  待機中のワーカー数
+    //--------------------------------------------------------------------------
+    pub(crate) fn num_sleepers( &self ) -> usize
+    {
+        self.sleepers.load(Ordering::SeqCst)
+    }
+
+    //--------------------------------------------------------------------------
+    //  ローカルキューの数をワーカー数に合わせて増やす
+    //--------------------------------------------------------------------------
+    pub(crate) fn grow( &self, num_workers: usize )
+    {
+        let mut locals = self.locals.write().unwrap();
+        while locals.len() < num_workers
+        {
+            locals.push(Mutex::new(Default::default()));
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューが閉じられたかどうか
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_closed( &self ) -> bool
+    {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドをワーカーとして登録
+    //--------------------------------------------------------------------------
+    pub(crate) fn register_worker( &self, index: usize )
+    {
+        CURRENT_WORKER.with(|cell| cell.set(Some((self.address(), index))));
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドのワーカー登録を解除
+    //--------------------------------------------------------------------------
+    pub(crate) fn unregister_worker( &self )
+    {
+        CURRENT_WORKER.with(|cell| cell.set(None));
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドがこのキューのワーカーであれば、そのインデックスを返す
+    //--------------------------------------------------------------------------
+    fn current_worker( &self ) -> Option<usize>
+    {
+        match CURRENT_WORKER.with(Cell::get)
+        {
+            Some((address, index)) if address == self.address() => Some(index),
+            _ => None,
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  現在のスレッドがこのキューのワーカーかどうか
+    //--------------------------------------------------------------------------
+    pub(crate) fn is_current_worker( &self ) -> bool
+    {
+        self.current_worker().is_some()
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューの識別に用いるアドレス
+    //--------------------------------------------------------------------------
+    fn address( &self ) -> usize
+    {
+        self as *const Self as usize
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブを追加
+    //--------------------------------------------------------------------------
+    pub(crate) fn push( &self, job: Job, priority: Priority ) -> Result<(), PushError>
+    {
+        match self.reserve()
+        {
+            Ok(()) =>
+            {
+                self.push_reserved(job, priority);
+                Ok(())
+            },
+            Err(ReserveError::Full) => Err(PushError::Full(job)),
+            Err(ReserveError::Closed) => Err(PushError::Closed(job)),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  上限を超えないようにジョブ数を予約
+    //  予約に成功した場合は、続けて `push_reserved()` でジョブを追加する
+    //--------------------------------------------------------------------------
+    pub(crate) fn reserve( &self ) -> Result<(), ReserveError>
+    {
+        let reserved = self.len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len|
+        {
+            if len < self.capacity { Some(len + 1) } else { None }
+        });
+        if reserved.is_err()
+        {
+            return Err(ReserveError::Full);
+        }
+
+        if self.is_closed()
+        {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return Err(ReserveError::Closed);
+        }
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------
+    //  上限を超えない範囲で最大 `n` 個のジョブ数をまとめて予約
+    //  予約できた数を返し、続けて `push_reserved_batch()` でジョブを追加する
+    //--------------------------------------------------------------------------
+    pub(crate) fn reserve_many( &self, n: usize ) -> Result<usize, ReserveError>
+    {
+        let reserved = self.len.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len|
+        {
+            if len < self.capacity { Some(len + n.min(self.capacity - len)) } else { None }
+        });
+        let num_reserved = match reserved
+        {
+            Ok(len) => n.min(self.capacity - len),
+            Err(_) => return Err(ReserveError::Full),
+        };
+
+        if self.is_closed()
+        {
+            self.len.fetch_sub(num_reserved, Ordering::SeqCst);
+            return Err(ReserveError::Closed);
+        }
+        Ok(num_reserved)
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブ数を予約するか、キューが一杯であれば `Waker` を登録
+    //--------------------------------------------------------------------------
+    pub(crate) fn reserve_or_register( &self, waker: &Waker ) -> Result<(), ReserveError>
+    {
+        //  予約を試みる前に待っていることを公開して、起床の取りこぼしを防ぐ
+        let mut sender_wakers = self.sender_wakers.lock().unwrap();
+        self.num_sender_wakers.store(sender_wakers.len() + 1, Ordering::SeqCst);
+        let result = self.reserve();
+        if let Err(ReserveError::Full) = result
+        {
+            sender_wakers.push(waker.clone());
+        }
+        self.num_sender_wakers.store(sender_wakers.len(), Ordering::SeqCst);
+        result
+    }
+
+    //--------------------------------------------------------------------------
+    //  予約済みのジョブを追加
+    //--------------------------------------------------------------------------
+    pub(crate) fn push_reserved( &self, job: Job, priority: Priority )
+    {
+        //  ワーカーの内部からはローカルキューに追加
+        let lane = priority.index();
+        self.lane_len[lane].fetch_add(1, Ordering::SeqCst);
+        match self.current_worker()
+        {
+            Some(index) => self.locals.read().unwrap()[index].lock().unwrap()[lane].push_back(job),
+            None => self.injector.lock().unwrap()[lane].push_back(job),
+        }
+
+        self.notify_one();
+    }
+
+    //--------------------------------------------------------------------------
+    //  `reserve_many()` で予約したジョブをまとめて追加
+    //  キューのロックと待機中のワーカーの起床はまとめて1回だけ行う
+    //--------------------------------------------------------------------------
+    pub(crate) fn push_reserved_batch( &self, jobs: Vec<Job>, priority: Priority )
+    {
+        let num_jobs = jobs.len();
+        if num_jobs == 0
+        {
+            return;
+        }
+
+        let lane = priority.index();
+        self.lane_len[lane].fetch_add(num_jobs, Ordering::SeqCst);
+        match self.current_worker()
+        {
+            Some(index) => self.locals.read().unwrap()[index].lock().unwrap()[lane].extend(jobs),
+            None => self.injector.lock().unwrap()[lane].extend(jobs),
+        }
+
+        if self.sleepers.load(Ordering::SeqCst) > 0
+        {
+            let _park = self.park.lock().unwrap();
+            if num_jobs == 1 { self.condvar.notify_one(); } else { self.condvar.notify_all(); }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブを取得
+    //  ジョブがなければタイムアウトまで待機する
+    //  タイムアウトが `None` の場合は通知があるまで待機する
+    //--------------------------------------------------------------------------
+    pub(crate) fn pop( &self, index: usize, timeout: Option<Duration> ) -> Pop
+    {
+        if let Some(job) = self.find_job(index)
+        {
+            return Pop::Job(job);
+        }
+
+        let mut park = self.park.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        let result = loop
+        {
+            //  待機する直前にジョブの有無を確認
+            if self.len() > 0
+            {
+                drop(park);
+                match self.find_job(index)
+                {
+                    Some(job) => break Pop::Job(job),
+                    None =>
+                    {
+                        //  他のワーカーに先に取得された場合は待機し直す
+                        //  ローカルキューのロックが競合して盗めなかった場合は、
+                        //  空回りしないように少し待機してから探し直す
+                        park = self.park.lock().unwrap();
+                        if self.len() > 0
+                        {
+                            park = self.condvar.wait_timeout(park, RETRY_BACKOFF).unwrap().0;
+                            continue;
+                        }
+                    },
+                }
+            }
+            if self.is_closed()
+            {
+                break Pop::Closed;
+            }
+
+            let timed_out = match timeout
+            {
+                Some(timeout) =>
+                {
+                    let (guard, wait_result) = self.condvar.wait_timeout(park, timeout).unwrap();
+                    park = guard;
+                    wait_result.timed_out()
+                },
+                None =>
+                {
+                    park = self.condvar.wait(park).unwrap();
+                    false
+                },
+            };
+            if self.len() < 1
+            {
+                if self.is_closed()
+                {
+                    break Pop::Closed;
+                }
+                if timed_out
+                {
+                    break Pop::Timeout;
+                }
+                break Pop::Notified;
+            }
+        };
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカースレッドから待機せずにジョブを取得
+    //  ワーカー以外のスレッドからは取得できない
+    //--------------------------------------------------------------------------
+    pub(crate) fn try_pop( &self ) -> Option<Job>
+    {
+        self.find_job(self.current_worker()?)
+    }
+
+    //--------------------------------------------------------------------------
+    //  優先度の高いレーンから順にジョブを探す
+    //  一定の間隔で低い優先度のレーンから順に探す
+    //--------------------------------------------------------------------------
+    fn find_job( &self, index: usize ) -> Option<Job>
+    {
+        let num_finds = NUM_FINDS.with(|cell|
+        {
+            cell.set(cell.get().wrapping_add(1));
+            cell.get()
+        });
+        let starving = num_finds.is_multiple_of(STARVATION_INTERVAL);
+
+        for n in 0..NUM_PRIORITIES
+        {
+            let lane = if starving { NUM_PRIORITIES - 1 - n } else { n };
+            if self.lane_len[lane].load(Ordering::SeqCst) < 1
+            {
+                continue;
+            }
+            if let Some(job) = self.find_job_in_lane(index, lane)
+            {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                self.lane_len[lane].fetch_sub(1, Ordering::SeqCst);
+                self.wake_senders();
+                return Some(job);
+            }
+        }
+        None
+    }
+
+    //--------------------------------------------------------------------------
+    //  ローカルキュー、インジェクタ、他のワーカーの順にレーンのジョブを探す
+    //--------------------------------------------------------------------------
+    fn find_job_in_lane( &self, index: usize, lane: usize ) -> Option<Job>
+    {
+        //  ロックのガードを文の終わりまで保持しないように1つずつ取得
+        let mut job = self.locals.read().unwrap()[index].lock().unwrap()[lane].pop_front();
+        if job.is_none()
+        {
+            job = self.injector.lock().unwrap()[lane].pop_front();
+        }
+        if job.is_none()
+        {
+            job = self.steal(index, lane);
+        }
+        job
+    }
+
+    //--------------------------------------------------------------------------
+    //  他のワーカーのローカルキューからレーンのジョブを盗む
+    //--------------------------------------------------------------------------
+    fn steal( &self, index: usize, lane: usize ) -> Option<Job>
+    {
+        let locals = self.locals.read().unwrap();
+        let num_workers = locals.len();
+        for offset in 1..num_workers
+        {
+            let victim = (index + offset) % num_workers;
+            let mut stolen =
+            {
+                let mut victim_lanes = match locals[victim].try_lock()
+                {
+                    Ok(guard) => guard,
+                    Err(_) => continue,
+                };
+                let victim_queue = &mut victim_lanes[lane];
+                let num_stolen = victim_queue.len().div_ceil(2);
+                let at = victim_queue.len() - num_stolen;
+                victim_queue.split_off(at)
+            };
+
+            if let Some(job) = stolen.pop_front()
+            {
+                //  残りは自身のローカルキューに移す
+                if !stolen.is_empty()
+                {
+                    locals[index].lock().unwrap()[lane].append(&mut stolen);
+                }
+                return Some(job);
+            }
+        }
+        None
+    }
+
+    //--------------------------------------------------------------------------
+    //  待機中のワーカーを1つ起床
+    //--------------------------------------------------------------------------
+    fn notify_one( &self )
+    {
+        if self.sleepers.load(Ordering::SeqCst) > 0
+        {
+            let _park = self.park.lock().unwrap();
+            self.condvar.notify_one();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューの空きを待っているタスクをすべて起床
+    //--------------------------------------------------------------------------
+    fn wake_senders( &self )
+    {
+        if self.num_sender_wakers.load(Ordering::SeqCst) < 1
+        {
+            return;
+        }
+
+        let sender_wakers =
+        {
+            let mut sender_wakers = self.sender_wakers.lock().unwrap();
+            self.num_sender_wakers.store(0, Ordering::SeqCst);
+            std::mem::take(&mut *sender_wakers)
+        };
+        for waker in sender_wakers
+        {
+            waker.wake();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューに残っているジョブをすべて破棄
+    //  破棄したジョブの数を返す
+    //--------------------------------------------------------------------------
+    pub(crate) fn clear( &self ) -> usize
+    {
+        let mut jobs: Vec<Job> = Vec::new();
+        for lane in 0..NUM_PRIORITIES
+        {
+            let num_jobs = jobs.len();
+            jobs.extend(self.injector.lock().unwrap()[lane].drain(..));
+            for local in self.locals.read().unwrap().iter()
+            {
+                jobs.extend(local.lock().unwrap()[lane].drain(..));
+            }
+            self.lane_len[lane].fetch_sub(jobs.len() - num_jobs, Ordering::SeqCst);
+        }
+        self.len.fetch_sub(jobs.len(), Ordering::SeqCst);
+        self.wake_senders();
+
+        //  ジョブのドロップはロックの外で行う
+        let num_discarded = jobs.len();
+        drop(jobs);
+        num_discarded
+    }
+
+    //--------------------------------------------------------------------------
+    //  待機中のワーカーをすべて起床
+    //--------------------------------------------------------------------------
+    pub(crate) fn notify_all( &self )
+    {
+        let _park = self.park.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューを閉じて、待機中のワーカーをすべて起床
+    //  残っているジョブはワーカーによって実行される
+    //--------------------------------------------------------------------------
+    pub(crate) fn close( &self )
+    {
+        {
+            let _park = self.park.lock().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+            self.condvar.notify_all();
+        }
+        self.wake_senders();
+    }
+}
@@ -0,0 +1,234 @@
+/*
+
+    非同期のジョブのスケジュール
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `schedule()` はキューが一杯のときにスリープしながら再試行するので、非同期タ
+    スクから呼び出すとエグゼキュータのスレッドごと停止してしまう。
+    `schedule_async()` はキューが一杯のときに `Waker` を登録して待ち、キューに
+    空きができたときに起床される。
+
+    ```rust
+    async fn produce( pool: &ThreadPool, data: Vec<Data> )
+    {
+        for item in data
+        {
+            pool.schedule_async(move || process(item)).await.unwrap();
+        }
+    }
+    ```
+
+    プールがパニックによって停止されていた場合は、ジョブのクロージャと一緒に
+    `TryScheduleError::Aborted` を返す。
+
+*/
+
+use crate::error::TryScheduleError;
+use crate::threadpool::ThreadPool;
+use crate::threadpool::priority::Priority;
+use crate::threadpool::queue::ReserveError;
+
+use core::fmt::{ Debug, Formatter };
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ Context, Poll };
+
+//------------------------------------------------------------------------------
+//  ScheduleFut
+//------------------------------------------------------------------------------
+pub struct ScheduleFut<'a, F>
+{
+    //  ジョブを追加するスレッドプール
+    pool: &'a ThreadPool,
+
+    //  ジョブの優先度
+    priority: Priority,
+
+    //  まだキューに追加していないジョブ
+    f: Option<F>,
+}
+
+//  ジョブのクロージャはピン留めせずにムーブするだけなので、常に `Unpin` でよい
+impl<F> Unpin for ScheduleFut<'_, F> {}
+
+impl<F> Future for ScheduleFut<'_, F>
+where
+    F: FnOnce() + Send + 'static,
+{
+    type Output = Result<(), TryScheduleError<F>>;
+
+    //--------------------------------------------------------------------------
+    //  poll
+    //--------------------------------------------------------------------------
+    fn poll( self: Pin<&mut Self>, cx: &mut Context<'_> ) -> Poll<Self::Output>
+    {
+        let this = self.get_mut();
+        let inner = &this.pool.inner;
+        match inner.queue.reserve_or_register(cx.waker())
+        {
+            Ok(()) =>
+            {
+                let f = this.f.take().expect("ScheduleFut polled after completion");
+                inner.queue.push_reserved(Box::new(f), this.priority);
+                let _ignored = inner.start_threads().and_then(|()| inner.grow_if_backlogged());
+                Poll::Ready(Ok(()))
+            },
+            Err(ReserveError::Closed) =>
+            {
+                let f = this.f.take().expect("ScheduleFut polled after completion");
+                Poll::Ready(Err(TryScheduleError::Aborted(f)))
+            },
+            Err(ReserveError::Full) => Poll::Pending,
+        }
+    }
+}
+
+impl<F> Debug for ScheduleFut<'_, F>
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "ScheduleFut{{{:?}, priority={:?}}}", self.pool, self.priority)
+    }
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  ジョブを非同期にスケジュール
+    //  キューが一杯の場合は空きができるまで待つ
+    //--------------------------------------------------------------------------
+    pub fn schedule_async<F>( &self, f: F ) -> ScheduleFut<'_, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.schedule_async_with_priority(Priority::Normal, f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  優先度を指定してジョブを非同期にスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_async_with_priority<F>( &self, priority: Priority, f: F ) -> ScheduleFut<'_, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ScheduleFut { pool: self, priority, f: Some(f) }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::error::TryScheduleError;
+    use crate::threadpool::ThreadPool;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{ Context, Poll, Waker };
+    use core::time::Duration;
+    use std::sync::atomic::{ AtomicBool, Ordering };
+    use std::sync::{ Arc, Barrier };
+    use std::task::Wake;
+
+    //--------------------------------------------------------------------------
+    //  起床されたかどうかを記録するWaker
+    //--------------------------------------------------------------------------
+    struct FlagWaker
+    {
+        woken: AtomicBool,
+    }
+
+    impl Wake for FlagWaker
+    {
+        fn wake( self: Arc<Self> )
+        {
+            self.woken.store(true, Ordering::Release);
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_async
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_async()
+    {
+        let pool = ThreadPool::builder("test").size(1).queue_capacity(1).build().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        pool.schedule(move || { barrier_clone.wait(); });
+
+        //  ワーカーが停止している間にキューを埋める
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.inner.queue.len() > 0
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+        assert_eq!(Ok(()), pool.try_schedule(|| {}));
+        let marker = Arc::new(());
+        let marker_clone = marker.clone();
+        let rejected = pool.try_schedule(move || drop(marker_clone)).unwrap_err();
+        assert!(matches!(rejected, TryScheduleError::QueueFull(_)));
+        assert_eq!(2, Arc::strong_count(&marker));
+        rejected.into_inner().unwrap()();
+        assert_eq!(1, Arc::strong_count(&marker));
+
+        let flag = Arc::new(FlagWaker { woken: AtomicBool::new(false) });
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut fut = pin!(pool.schedule_async(move || sender.send(()).unwrap()));
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+        //  ジョブが取り出されると起床される
+        barrier.wait();
+        while !flag.woken.load(Ordering::Acquire)
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+        let mut result = fut.as_mut().poll(&mut cx);
+        while result.is_pending()
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::yield_now();
+            result = fut.as_mut().poll(&mut cx);
+        }
+        assert!(matches!(result, Poll::Ready(Ok(()))));
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_async_aborted
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_async_aborted()
+    {
+        let pool = ThreadPool::builder("test").size(1).abort_on_panic(true).build().unwrap();
+        pool.schedule(|| panic!("job panicked"));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !pool.is_aborted()
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::yield_now();
+        }
+
+        let waker = Waker::from(Arc::new(FlagWaker { woken: AtomicBool::new(false) }));
+        let mut cx = Context::from_waker(&waker);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let fut = pin!(pool.schedule_async(move || sender.send(2).unwrap()));
+        match fut.poll(&mut cx)
+        {
+            Poll::Ready(Err(TryScheduleError::Aborted(f))) => f(),
+            _ => panic!("expected TryScheduleError::Aborted"),
+        }
+        assert_eq!(2, receiver.recv().unwrap());
+    }
+}
@@ -0,0 +1,400 @@
+/*
+
+    スコープ付きのジョブ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `std::thread::scope()` と同様に、呼び出し元のスタックを借用するジョブをプー
+    ルのワーカースレッドで実行する。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 4).unwrap();
+    let mut data = vec![1, 2, 3, 4];
+    pool.scope(|s|
+    {
+        for value in data.iter_mut()
+        {
+            s.spawn(move || *value *= 2);
+        }
+    });
+    assert_eq!(vec![2, 4, 6, 8], data);
+    ```
+
+    `scope()` はスコープ内で生成されたジョブがすべて完了するまで戻らない。いず
+    れかのジョブがパニックになった場合は、すべてのジョブの完了を待ってから呼び
+    出し元でパニックを再開する。
+
+    ワーカースレッドの内部から `scope()` を呼び出した場合は、待機中にキューのジ
+    ョブを実行してデッドロックを防ぐ。
+
+    プールが停止されてジョブが実行されずに破棄された場合は、そのジョブがパニッ
+    クになったものとして扱う。プールが劣化状態でスレッドがない場合は、ジョブを
+    呼び出し元のスレッドで実行する。
+
+*/
+
+use crate::threadpool::ThreadPool;
+use crate::threadpool::queue::Job;
+
+use core::any::Any;
+use core::marker::PhantomData;
+use core::time::Duration;
+use std::panic::{ catch_unwind, resume_unwind, AssertUnwindSafe };
+use std::sync::{ Arc, Condvar, Mutex };
+
+//  実行されずに破棄されたジョブのパニックメッセージ
+const DISCARDED: &str = "scoped job was discarded because the thread pool was shut down";
+
+//------------------------------------------------------------------------------
+//  スコープの状態
+//------------------------------------------------------------------------------
+struct ScopeState
+{
+    //  完了していないジョブの数
+    num_pending: Mutex<usize>,
+
+    //  すべてのジョブが完了したことを通知する
+    all_done: Condvar,
+
+    //  最初にパニックになったジョブのペイロード
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+impl ScopeState
+{
+    //--------------------------------------------------------------------------
+    //  ジョブの完了を記録
+    //--------------------------------------------------------------------------
+    fn job_completed( &self, result: Result<(), Box<dyn Any + Send + 'static>> )
+    {
+        if let Err(payload) = result
+        {
+            self.record_panic(payload);
+        }
+
+        let mut num_pending = self.num_pending.lock().unwrap();
+        *num_pending -= 1;
+        if *num_pending == 0
+        {
+            self.all_done.notify_all();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  最初のパニックを記録
+    //--------------------------------------------------------------------------
+    fn record_panic( &self, payload: Box<dyn Any + Send + 'static> )
+    {
+        let mut panic = self.panic.lock().unwrap();
+        if panic.is_none()
+        {
+            *panic = Some(payload);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  スコープ内のジョブ
+//  実行されずに破棄された場合も、ドロップ時に完了を記録する
+//------------------------------------------------------------------------------
+struct ScopedJob<F>
+{
+    //  実行するクロージャ
+    f: Option<F>,
+
+    //  実行結果
+    result: Option<Result<(), Box<dyn Any + Send + 'static>>>,
+
+    //  スコープの状態
+    state: Arc<ScopeState>,
+}
+
+impl<F: FnOnce()> ScopedJob<F>
+{
+    //--------------------------------------------------------------------------
+    //  ジョブを実行
+    //--------------------------------------------------------------------------
+    fn run( mut self )
+    {
+        if let Some(f) = self.f.take()
+        {
+            self.result = Some(catch_unwind(AssertUnwindSafe(f)));
+        }
+    }
+}
+
+impl<F> Drop for ScopedJob<F>
+{
+    //--------------------------------------------------------------------------
+    //  drop
+    //  借用を含むクロージャは、完了を記録してスコープが終了する前に破棄する
+    //--------------------------------------------------------------------------
+    fn drop( &mut self )
+    {
+        let result = match self.result.take()
+        {
+            Some(result) => result,
+            None =>
+            {
+                //  実行されずに破棄された場合はパニックとして記録
+                let f = self.f.take();
+                let payload = match catch_unwind(AssertUnwindSafe(move || drop(f)))
+                {
+                    Ok(()) => Box::new(DISCARDED),
+                    Err(payload) => payload,
+                };
+                Err(payload)
+            },
+        };
+        self.state.job_completed(result);
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Scope
+//------------------------------------------------------------------------------
+pub struct Scope<'scope, 'env: 'scope>
+{
+    //  ジョブを実行するスレッドプール
+    pool: &'scope ThreadPool,
+
+    //  スコープの状態
+    state: Arc<ScopeState>,
+
+    //  'scopeと'envを不変にする
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env>
+{
+    //--------------------------------------------------------------------------
+    //  スコープ内でジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn spawn<F>( &'scope self, f: F )
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.num_pending.lock().unwrap() += 1;
+
+        let job = ScopedJob { f: Some(f), result: None, state: self.state.clone() };
+
+        //  劣化状態でスレッドがないプールでは、スレッドの起動を待たずに呼び出し
+        //  元で実行する
+        if self.pool.is_degraded() && self.pool.num_live_threads() < 1
+        {
+            job.run();
+            return;
+        }
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+
+        //  SAFETY: `ThreadPool::scope()` はすべてのジョブが実行されるか破棄され
+        //  るまで戻らないので、ジョブが借用するデータはその前に破棄されない
+        let job: Job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.schedule(job);
+    }
+
+    //--------------------------------------------------------------------------
+    //  すべてのジョブが完了するまで待つ
+    //--------------------------------------------------------------------------
+    fn wait( &self )
+    {
+        let is_worker = self.pool.inner.queue.is_current_worker();
+        let mut num_pending = self.state.num_pending.lock().unwrap();
+        while *num_pending > 0
+        {
+            if !is_worker
+            {
+                num_pending = self.state.all_done.wait(num_pending).unwrap();
+                continue;
+            }
+
+            //  ワーカースレッドではキューのジョブを実行しながら待つ
+            //  パニックハンドラがパニックになった場合も、スコープのジョブが完了
+            //  するまで待ってから呼び出し元で再開する
+            drop(num_pending);
+            let inner = &self.pool.inner;
+            match catch_unwind(AssertUnwindSafe(|| inner.run_pending_job()))
+            {
+                Ok(true) => {},
+                Ok(false) => std::thread::yield_now(),
+                Err(payload) => self.state.record_panic(payload),
+            }
+            num_pending = self.state.num_pending.lock().unwrap();
+            if *num_pending > 0
+            {
+                num_pending = self
+                    .state
+                    .all_done
+                    .wait_timeout(num_pending, Duration::from_millis(1))
+                    .unwrap()
+                    .0;
+            }
+        }
+    }
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  呼び出し元のスタックを借用するジョブのスコープを生成
+    //  スコープ内のジョブがすべて完了するまで戻らない
+    //--------------------------------------------------------------------------
+    pub fn scope<'env, F, R>( &self, f: F ) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope
+        {
+            pool: self,
+            state: Arc::new(ScopeState
+            {
+                num_pending: Mutex::new(0),
+                all_done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        //  クロージャがパニックになった場合もジョブの完了を待つ
+        let result = catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        let job_panic = scope.state.panic.lock().unwrap().take();
+        match (result, job_panic)
+        {
+            (Err(payload), _) | (Ok(_), Some(payload)) => resume_unwind(payload),
+            (Ok(value), None) => value,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::DISCARDED;
+    use crate::threadpool::ThreadPool;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    //--------------------------------------------------------------------------
+    //  test_scope_borrow
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_scope_borrow()
+    {
+        let pool = ThreadPool::new("test", 4).unwrap();
+        let mut data: Vec<usize> = (0..100).collect();
+        let counter = AtomicUsize::new(0);
+        pool.scope(|s|
+        {
+            for chunk in data.chunks_mut(10)
+            {
+                let counter = &counter;
+                s.spawn(move ||
+                {
+                    for value in chunk.iter_mut()
+                    {
+                        *value *= 2;
+                    }
+                    counter.fetch_add(1, Ordering::AcqRel);
+                });
+            }
+        });
+        assert_eq!(10, counter.load(Ordering::Acquire));
+        assert_eq!((0..100).map(|n| n * 2).collect::<Vec<usize>>(), data);
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_scope_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_scope_panic()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        let counter = AtomicUsize::new(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+        {
+            pool.scope(|s|
+            {
+                s.spawn(|| panic!("scoped job panicked"));
+                for _ in 0..10
+                {
+                    s.spawn(|| { counter.fetch_add(1, Ordering::AcqRel); });
+                }
+            });
+        }));
+        assert_eq!(10, counter.load(Ordering::Acquire));
+        assert_eq!(&"scoped job panicked", result.unwrap_err().downcast_ref::<&str>().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_scope_discarded
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_scope_discarded()
+    {
+        let pool = ThreadPool::builder("test").size(1).abort_on_panic(true).build().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule(move ||
+        {
+            receiver.recv().unwrap();
+            panic!("job panicked");
+        });
+
+        //  キューに残っていたジョブはプールの停止時に破棄される
+        let counter = AtomicUsize::new(0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+        {
+            pool.scope(|s|
+            {
+                for _ in 0..10
+                {
+                    s.spawn(|| { counter.fetch_add(1, Ordering::AcqRel); });
+                }
+                sender.send(()).unwrap();
+            });
+        }));
+        assert_eq!(0, counter.load(Ordering::Acquire));
+        assert_eq!(&DISCARDED, result.unwrap_err().downcast_ref::<&str>().unwrap());
+
+        //  停止した後のプールにはジョブを追加できない
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+        {
+            pool.scope(|s| s.spawn(|| { counter.fetch_add(1, Ordering::AcqRel); }));
+        }));
+        assert_eq!(0, counter.load(Ordering::Acquire));
+        assert_eq!(&DISCARDED, result.unwrap_err().downcast_ref::<&str>().unwrap());
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_scope_from_worker
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_scope_from_worker()
+    {
+        let pool = std::sync::Arc::new(ThreadPool::new("test", 1).unwrap());
+        let pool_clone = pool.clone();
+        let sum = pool.spawn(move ||
+        {
+            let values = [1, 2, 3, 4];
+            let sum = AtomicUsize::new(0);
+            pool_clone.scope(|s|
+            {
+                for value in values.iter()
+                {
+                    let sum = &sum;
+                    s.spawn(move || { sum.fetch_add(*value, Ordering::AcqRel); });
+                }
+            });
+            sum.into_inner()
+        });
+        assert_eq!(10, sum.join().unwrap());
+    }
+}
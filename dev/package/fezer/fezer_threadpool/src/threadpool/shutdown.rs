@@ -0,0 +1,203 @@
+/*
+
+    スレッドプールの停止
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `shutdown()` はキューを閉じて待機中のワーカーをすぐに起床させ、すべてのワ
+    ーカーが停止するまで待つ。
+
+    - `ShutdownMode::Drain` はキューに残っているジョブをすべて実行してから停止
+      する
+    - `ShutdownMode::Discard` はキューに残っているジョブを実行せずに破棄し、実
+      行中のジョブの完了を待って停止する
+
+    破棄されたジョブの `JoinHandle` は `JoinError::Dropped` を返す。
+
+*/
+
+use crate::error::ShutdownError;
+use crate::threadpool::ThreadPool;
+
+use core::time::Duration;
+use std::time::Instant;
+
+//------------------------------------------------------------------------------
+//  停止時にキューに残っているジョブの扱い
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode
+{
+    //  キューに残っているジョブをすべて実行してから停止
+    Drain,
+
+    //  キューに残っているジョブを破棄して停止
+    Discard,
+}
+
+//------------------------------------------------------------------------------
+//  停止の結果
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport
+{
+    //  実行されずに破棄されたジョブの数
+    pub num_discarded: usize,
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  スレッドプールを停止して、スレッドがすべて停止するまで待つ
+    //--------------------------------------------------------------------------
+    pub fn shutdown( self, mode: ShutdownMode ) -> ShutdownReport
+    {
+        let inner = self.inner.clone();
+        let num_discarded = self.close(mode);
+        inner.wait_stopped(None);
+        ShutdownReport { num_discarded }
+    }
+
+    //--------------------------------------------------------------------------
+    //  スレッドプールを停止
+    //  タイムアウトを上限としてスレッドの停止を待つ
+    //--------------------------------------------------------------------------
+    pub fn shutdown_timeout( self, mode: ShutdownMode, timeout: Duration )
+        -> Result<ShutdownReport, ShutdownError>
+    {
+        let inner = self.inner.clone();
+        let num_discarded = self.close(mode);
+        if inner.wait_stopped(Some(Instant::now() + timeout))
+        {
+            Ok(ShutdownReport { num_discarded })
+        }
+        else
+        {
+            Err(ShutdownError::Timeout
+            {
+                num_running: inner.num_live_threads(),
+                num_discarded,
+            })
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  キューを閉じてスレッドプールをドロップ
+    //  破棄したジョブの数を返す
+    //--------------------------------------------------------------------------
+    fn close( self, mode: ShutdownMode ) -> usize
+    {
+        self.inner.close();
+        let num_discarded = match mode
+        {
+            ShutdownMode::Drain => 0,
+            ShutdownMode::Discard => self.inner.queue.clear(),
+        };
+        drop(self);
+        num_discarded
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::{ ShutdownMode, ShutdownReport };
+    use crate::error::{ JoinError, ShutdownError };
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::{ Arc, Barrier };
+    use std::time::Instant;
+
+    //--------------------------------------------------------------------------
+    //  test_shutdown_drain
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_shutdown_drain()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100
+        {
+            let counter_clone = counter.clone();
+            pool.schedule(move || { counter_clone.fetch_add(1, Ordering::AcqRel); });
+        }
+        assert_eq!(ShutdownReport { num_discarded: 0 }, pool.shutdown(ShutdownMode::Drain));
+        assert_eq!(100, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_shutdown_discard
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_shutdown_discard()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        let running = pool.spawn(move ||
+        {
+            barrier_clone.wait();
+            std::thread::sleep(Duration::from_millis(50));
+        });
+        barrier.wait();
+
+        let pending: Vec<_> = (0..10).map(|n| pool.spawn(move || n)).collect();
+        assert_eq!(ShutdownReport { num_discarded: 10 }, pool.shutdown(ShutdownMode::Discard));
+        assert_eq!(Ok(()), running.join().map_err(|_| ()));
+        for handle in pending
+        {
+            assert_eq!(JoinError::Dropped, handle.join().unwrap_err());
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_shutdown_wakes_idle_workers
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_shutdown_wakes_idle_workers()
+    {
+        let pool = ThreadPool::builder("test")
+            .size(4)
+            .keep_alive(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        let start = Instant::now();
+        pool.shutdown(ShutdownMode::Drain);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_shutdown_timeout
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_shutdown_timeout()
+    {
+        //  ワーカーがジョブを実行中になってから停止し、タイムアウトするまでジョ
+        //  ブを完了させない
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        pool.schedule(move ||
+        {
+            barrier_clone.wait();
+            let _ = receiver.recv();
+        });
+        barrier.wait();
+
+        assert_eq!
+        (
+            Err(ShutdownError::Timeout { num_running: 1, num_discarded: 0 }),
+            pool.shutdown_timeout(ShutdownMode::Drain, Duration::from_millis(50))
+        );
+        drop(sender);
+    }
+}
@@ -0,0 +1,185 @@
+/*
+
+    スレッドプールの統計情報
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    `stats()` はプールの状態のスナップショットを返す。
+
+    ```rust
+    let stats = pool.stats();
+    if stats.num_queued > 1000 && stats.num_idle == 0
+    {
+        alert_saturated(&stats);
+    }
+    ```
+
+    各値はアトミック変数から個別に読み出すので、値の間の厳密な整合性は保証され
+    ない。`spawn()` と `scope()` のジョブはパニックを呼び出し元に返すので、パニ
+    ックになった場合も完了したジョブとして数える。
+
+    ワーカーごとの統計情報はスロット単位で記録される。パニックで停止したワーカ
+    ーのスロットを引き継いだワーカーは、累積の実行時間も引き継ぐ。
+
+*/
+
+use crate::threadpool::ThreadPool;
+
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+//------------------------------------------------------------------------------
+//  ワーカーの統計情報
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerStats
+{
+    //  ワーカーのスロットのインデックス
+    pub index: usize,
+
+    //  ワーカーが起動しているかどうか
+    pub alive: bool,
+
+    //  ジョブを実行中かどうか
+    pub busy: bool,
+
+    //  ジョブの実行に費やした累積時間
+    pub busy_time: Duration,
+}
+
+//------------------------------------------------------------------------------
+//  スレッドプールの統計情報
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadPoolStats
+{
+    //  キューに溜まっているジョブの数
+    pub num_queued: usize,
+
+    //  生存中のワーカーの数
+    pub num_live: usize,
+
+    //  ジョブを実行中のワーカーの数
+    pub num_busy: usize,
+
+    //  ジョブを待っているワーカーの数
+    pub num_idle: usize,
+
+    //  完了したジョブの数
+    pub num_completed: usize,
+
+    //  パニックになったジョブの数
+    pub num_panicked: usize,
+
+    //  パニックで停止して再起動の対象になったワーカーの数
+    pub num_respawned: usize,
+
+    //  CPUへのピン留めに失敗したワーカーの数
+    pub num_pin_failures: usize,
+
+    //  ワーカーごとの統計情報
+    pub workers: Vec<WorkerStats>,
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  統計情報のスナップショットを取得
+    //--------------------------------------------------------------------------
+    pub fn stats( &self ) -> ThreadPoolStats
+    {
+        let inner = &self.inner;
+        let workers: Vec<WorkerStats> = inner
+            .slots
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| WorkerStats
+            {
+                index,
+                alive: slot.in_use.load(Ordering::Acquire),
+                busy: slot.busy.load(Ordering::Acquire),
+                busy_time: Duration::from_nanos(slot.busy_nanos.load(Ordering::Acquire)),
+            })
+            .collect();
+
+        let num_live = inner.num_live_threads();
+        let num_busy = workers.iter().filter(|worker| worker.busy).count();
+        ThreadPoolStats
+        {
+            num_queued: inner.queue.len(),
+            num_live,
+            num_busy,
+            num_idle: num_live.saturating_sub(num_busy),
+            num_completed: inner.num_completed.get(),
+            num_panicked: inner.num_panicked.get(),
+            num_respawned: inner.num_respawned.get(),
+            num_pin_failures: inner.num_pin_failures.get(),
+            workers,
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+    use std::sync::{ Arc, Barrier };
+
+    //--------------------------------------------------------------------------
+    //  test_stats
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_stats()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let barrier_clone = barrier.clone();
+        let handle = pool.spawn(move ||
+        {
+            std::thread::sleep(Duration::from_millis(20));
+            barrier_clone.wait();
+            barrier_clone.wait();
+        });
+
+        //  ジョブの実行中
+        barrier.wait();
+        let stats = pool.stats();
+        assert_eq!(2, stats.num_live);
+        assert_eq!(1, stats.num_busy);
+        assert_eq!(1, stats.num_idle);
+        assert_eq!(2, stats.workers.len());
+        barrier.wait();
+        handle.join().unwrap();
+
+        pool.schedule(|| panic!("job panicked"));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule(move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        //  完了とパニックの数は実行を終えてから記録される
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let stats = loop
+        {
+            let stats = pool.stats();
+            if stats.num_completed == 2 && stats.num_panicked == 1 && stats.num_busy == 0
+            {
+                break stats;
+            }
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(0, stats.num_queued);
+        assert_eq!(0, stats.num_respawned);
+        assert_eq!(0, stats.num_pin_failures);
+        let busy_time: Duration = stats.workers.iter().map(|worker| worker.busy_time).sum();
+        assert!(busy_time >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,260 @@
+/*
+
+    ワーカーの監視
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    パニックで停止したワーカーの代わりのスレッドを起動する。
+
+    アイドル状態のワーカーは定期的に起床せずに待機し続けるので、停止したワーカ
+    ーの再起動はワーカー自身ではなく監視スレッドが行う。停止したワーカーが監視
+    スレッドに通知し、監視スレッドが最小スレッド数に達するまでスレッドを起動す
+    る。プールが劣化状態になったときも監視スレッドに通知される。
+
+    起動に失敗した場合は `restart_backoff` の初期値から始めて、失敗するたびに間
+    隔を2倍にしながら上限まで延ばして再試行する。起動に成功すると間隔は初期値に
+    戻る。
+
+    監視スレッドは最初にワーカーが停止したときに起動し、プールの停止とともに終
+    了する。監視スレッドを起動できなかった場合は、停止したワーカーが直接再起動
+    を試みる。
+
+*/
+
+use crate::threadpool::inner::Inner;
+
+use std::sync::{ Arc, Condvar, Mutex, Weak };
+
+//------------------------------------------------------------------------------
+//  監視スレッドの状態
+//------------------------------------------------------------------------------
+struct SupervisorState
+{
+    //  再起動が要求されているかどうか
+    pending: bool,
+
+    //  監視スレッドが起動しているかどうか
+    started: bool,
+
+    //  プールが停止したかどうか
+    closed: bool,
+}
+
+//------------------------------------------------------------------------------
+//  監視スレッドと共有する状態
+//------------------------------------------------------------------------------
+struct SupervisorShared
+{
+    state: Mutex<SupervisorState>,
+    condvar: Condvar,
+}
+
+impl SupervisorShared
+{
+    //--------------------------------------------------------------------------
+    //  監視スレッドの処理
+    //--------------------------------------------------------------------------
+    fn run( self: Arc<Self>, inner: Weak<Inner> )
+    {
+        let Some((initial, max)) = inner.upgrade().map(|inner| inner.restart_backoff()) else
+        {
+            return;
+        };
+        let mut backoff = initial;
+
+        let mut state = self.state.lock().unwrap();
+        loop
+        {
+            while !state.pending && !state.closed
+            {
+                state = self.condvar.wait(state).unwrap();
+            }
+            if state.closed
+            {
+                return;
+            }
+            state.pending = false;
+
+            //  ロックの外でスレッドを起動
+            drop(state);
+            let Some(inner) = inner.upgrade() else { return };
+            let result = inner.start_threads();
+            drop(inner);
+            state = self.state.lock().unwrap();
+
+            //  失敗した場合は間隔を延ばしながら再試行
+            if result.is_err() && !state.closed
+            {
+                state.pending = true;
+                state = self.condvar.wait_timeout(state, backoff).unwrap().0;
+                backoff = backoff.saturating_mul(2).min(max);
+            }
+            else
+            {
+                backoff = initial;
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Supervisor
+//------------------------------------------------------------------------------
+pub(crate) struct Supervisor
+{
+    shared: Arc<SupervisorShared>,
+}
+
+impl Supervisor
+{
+    //--------------------------------------------------------------------------
+    //  新しい監視を生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            shared: Arc::new(SupervisorShared
+            {
+                state: Mutex::new(SupervisorState
+                {
+                    pending: false,
+                    started: false,
+                    closed: false,
+                }),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  ワーカーの停止を通知して再起動を要求
+    //  必要であれば監視スレッドを起動する
+    //--------------------------------------------------------------------------
+    pub(crate) fn request_respawn( &self, inner: &Arc<Inner> )
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed
+        {
+            return;
+        }
+        state.pending = true;
+        self.shared.condvar.notify_all();
+        if state.started
+        {
+            return;
+        }
+        state.started = true;
+        drop(state);
+
+        let shared = self.shared.clone();
+        let weak = Arc::downgrade(inner);
+        let spawned = std::thread::Builder::new()
+            .name(format!("{}-supervisor", inner.name))
+            .spawn(move || shared.run(weak));
+
+        //  起動に失敗した場合は直接再起動を試み、次の要求時に再試行する
+        if spawned.is_err()
+        {
+            self.shared.state.lock().unwrap().started = false;
+            let _ignored = inner.start_threads();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  監視を閉じて、監視スレッドを停止
+    //--------------------------------------------------------------------------
+    pub(crate) fn close( &self )
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.condvar.notify_all();
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+
+    //--------------------------------------------------------------------------
+    //  test_supervisor_respawn
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_supervisor_respawn()
+    {
+        let pool = ThreadPool::builder("test")
+            .size(2)
+            .panic_handler(|_, _| panic!("respawn worker"))
+            .build()
+            .unwrap();
+        pool.schedule(|| panic!("job panicked"));
+
+        //  新しいジョブをスケジュールしなくても再起動される
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while pool.stats().num_respawned < 1 || pool.num_live_threads() < 2
+        {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        //  アイドル状態のワーカーはプールのドロップとともにすぐに停止する
+        let started = std::time::Instant::now();
+        pool.join();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_pool_degraded
+    //--------------------------------------------------------------------------
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pool_degraded()
+    {
+        use crate::error::{ StartThreadsError, TryScheduleError };
+        use std::sync::Arc;
+        use std::sync::atomic::{ AtomicUsize, Ordering };
+
+        //  スタックを確保できずにスレッドの起動が必ず失敗する
+        let num_degraded = Arc::new(AtomicUsize::new(0));
+        let num_degraded_clone = num_degraded.clone();
+        let pool = ThreadPool::builder("test")
+            .size_range(0, 1)
+            .stack_size(1 << 50)
+            .max_spawn_failures(3)
+            .restart_backoff(Duration::from_millis(1), Duration::from_millis(5))
+            .on_degraded(move |_| { num_degraded_clone.fetch_add(1, Ordering::AcqRel); })
+            .build()
+            .unwrap();
+        assert!(matches!(pool.set_size(1), Err(StartThreadsError::NoThreads(_))));
+        assert!(!pool.is_degraded());
+
+        //  劣化状態になるとスレッドの起動を待ち続けずにジョブをキューに追加する
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        pool.schedule(|| {});
+        assert!(std::time::Instant::now() < deadline);
+        assert!(pool.is_degraded());
+        assert_eq!(1, num_degraded.load(Ordering::Acquire));
+        assert!(matches!(pool.try_schedule(|| {}), Err(TryScheduleError::PoolDegraded(_))));
+        assert!(pool.schedule_batch((0..3).map(|_| || {})).is_empty());
+        assert_eq!(5, pool.inner.queue.len());
+
+        //  スコープのジョブは呼び出し元で実行される
+        let mut values = [1, 2, 3];
+        pool.scope(|s|
+        {
+            for value in values.iter_mut()
+            {
+                s.spawn(move || *value *= 2);
+            }
+        });
+        assert_eq!([2, 4, 6], values);
+        assert_eq!(vec![2, 4, 6], pool.par_map(vec![1, 2, 3], |n| n * 2));
+    }
+}
@@ -0,0 +1,597 @@
+/*
+
+    遅延ジョブと周期ジョブ
+
+    ----------------------------------------------------------------------------
+
+    # 概要
+
+    指定した時間の経過後や時刻に、ジョブをプールのキューに追加する。
+
+    ```rust
+    let pool = fezer_threadpool::ThreadPool::new("worker", 4).unwrap();
+    let handle = pool.schedule_after(Duration::from_secs(5), || retry_request());
+    let ticker = pool.schedule_every(Duration::from_secs(1), || report_metrics());
+    handle.cancel();
+    ```
+
+    期限はプールごとに1つのタイマースレッドが管理する。タイマースレッドは最初
+    の登録時に起動し、期限が来たジョブを通常の優先度でキューに追加する。キュー
+    が一杯の場合は少し待ってから再試行する。
+
+    # 周期ジョブ
+
+    周期ジョブは登録から1周期後に最初に実行され、実行が完了してから次の実行が
+    登録されるので、同じ周期ジョブが並行して実行されることはない。
+
+    - `Periodic::FixedRate` は最初の期限から周期の整数倍の時刻に実行する。実行
+      が遅れた場合は、遅れを取り戻すまで続けて実行する
+    - `Periodic::FixedDelay` は実行の完了から周期の経過後に次を実行する
+
+    周期ジョブがパニックになった場合も、パニックハンドラに通知したうえで実行を
+    続ける。
+
+    # キャンセル
+
+    `TimerHandle::cancel()` でキャンセルしたジョブは実行されない。既に実行中の
+    ジョブは中断されない。キャンセルしたジョブのクロージャは期限が来たときに破
+    棄される。プールを停止すると、期限が来ていないジョブはすべて破棄される。
+
+*/
+
+use crate::threadpool::ThreadPool;
+use crate::threadpool::inner::Inner;
+use crate::threadpool::priority::Priority;
+use crate::threadpool::queue::{ Job, PushError };
+
+use core::cmp::{ Ordering as CmpOrdering, Reverse };
+use core::fmt::{ Debug, Formatter };
+use core::sync::atomic::{ AtomicBool, Ordering };
+use core::time::Duration;
+use std::collections::BinaryHeap;
+use std::panic::{ catch_unwind, resume_unwind, AssertUnwindSafe };
+use std::sync::{ Arc, Condvar, Mutex, Weak };
+use std::time::Instant;
+
+//  キューが一杯だった場合に再試行するまでの時間
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+//  期限が `Instant` で表せないときに待つ時間（約30年）
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+//------------------------------------------------------------------------------
+//  基準の時刻から指定した時間が経過した時刻
+//  `Instant` で表せない場合は十分に先の時刻にする
+//------------------------------------------------------------------------------
+fn deadline_after( base: Instant, duration: Duration ) -> Instant
+{
+    base.checked_add(duration).unwrap_or_else(|| Instant::now() + FAR_FUTURE)
+}
+
+//------------------------------------------------------------------------------
+//  周期ジョブの実行間隔の扱い
+//------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Periodic
+{
+    //  最初の期限から周期の整数倍の時刻に実行
+    #[default]
+    FixedRate,
+
+    //  実行の完了から周期の経過後に実行
+    FixedDelay,
+}
+
+//------------------------------------------------------------------------------
+//  タイマーに登録されるジョブ
+//------------------------------------------------------------------------------
+enum TimerJob
+{
+    //  1回だけ実行するジョブ
+    Once(Job),
+
+    //  周期的に実行するジョブ
+    Periodic
+    {
+        f: Box<dyn FnMut() + Send + 'static>,
+        period: Duration,
+        mode: Periodic,
+    },
+}
+
+//------------------------------------------------------------------------------
+//  タイマーのエントリ
+//------------------------------------------------------------------------------
+struct Entry
+{
+    //  キューに追加する時刻
+    deadline: Instant,
+
+    //  同じ期限のエントリを登録順に並べるための通し番号
+    seq: u64,
+
+    //  キャンセルされたかどうか
+    cancelled: Arc<AtomicBool>,
+
+    //  実行するジョブ
+    job: TimerJob,
+}
+
+impl PartialEq for Entry
+{
+    //--------------------------------------------------------------------------
+    //  eq
+    //--------------------------------------------------------------------------
+    fn eq( &self, other: &Self ) -> bool
+    {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry
+{
+    //--------------------------------------------------------------------------
+    //  partial_cmp
+    //--------------------------------------------------------------------------
+    fn partial_cmp( &self, other: &Self ) -> Option<CmpOrdering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry
+{
+    //--------------------------------------------------------------------------
+    //  cmp
+    //--------------------------------------------------------------------------
+    fn cmp( &self, other: &Self ) -> CmpOrdering
+    {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+//------------------------------------------------------------------------------
+//  タイマーの状態
+//------------------------------------------------------------------------------
+struct TimerState
+{
+    //  期限の早い順に並んだエントリ
+    entries: BinaryHeap<Reverse<Entry>>,
+
+    //  次のエントリの通し番号
+    next_seq: u64,
+
+    //  タイマースレッドを起動したかどうか
+    started: bool,
+
+    //  タイマーが閉じられたかどうか
+    closed: bool,
+}
+
+//------------------------------------------------------------------------------
+//  タイマースレッドと共有する状態
+//------------------------------------------------------------------------------
+struct TimerShared
+{
+    state: Mutex<TimerState>,
+
+    //  エントリの追加と、タイマーが閉じられたことを通知する
+    condvar: Condvar,
+}
+
+impl TimerShared
+{
+    //--------------------------------------------------------------------------
+    //  エントリを追加
+    //  タイマースレッドを起動する必要があれば `true` を返す
+    //--------------------------------------------------------------------------
+    fn insert( &self, deadline: Instant, cancelled: Arc<AtomicBool>, job: TimerJob ) -> bool
+    {
+        let mut state = self.state.lock().unwrap();
+        if state.closed
+        {
+            return false;
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.entries.push(Reverse(Entry { deadline, seq, cancelled, job }));
+        self.condvar.notify_one();
+
+        let start = !state.started;
+        state.started = true;
+        start
+    }
+
+    //--------------------------------------------------------------------------
+    //  タイマースレッドの処理
+    //--------------------------------------------------------------------------
+    fn run( self: Arc<Self>, inner: Weak<Inner> )
+    {
+        let mut state = self.state.lock().unwrap();
+        while !state.closed
+        {
+            let now = Instant::now();
+            let deadline = match state.entries.peek()
+            {
+                Some(Reverse(entry)) => entry.deadline,
+                None =>
+                {
+                    state = self.condvar.wait(state).unwrap();
+                    continue;
+                },
+            };
+            if deadline > now
+            {
+                state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+
+            //  ロックの外でキューに追加
+            let Reverse(entry) = state.entries.pop().unwrap();
+            drop(state);
+            let Some(inner) = inner.upgrade() else { return };
+            self.dispatch(&inner, entry);
+            drop(inner);
+            state = self.state.lock().unwrap();
+        }
+
+        //  残りのエントリはロックの外で破棄
+        let entries = std::mem::take(&mut state.entries);
+        drop(state);
+        drop(entries);
+    }
+
+    //--------------------------------------------------------------------------
+    //  期限が来たエントリのジョブをキューに追加
+    //--------------------------------------------------------------------------
+    fn dispatch( self: &Arc<Self>, inner: &Arc<Inner>, entry: Entry )
+    {
+        if entry.cancelled.load(Ordering::Acquire)
+        {
+            return;
+        }
+
+        let cancelled = entry.cancelled.clone();
+        let job: Job = match entry.job
+        {
+            TimerJob::Once(f) => Box::new(move ||
+            {
+                if !cancelled.load(Ordering::Acquire)
+                {
+                    f();
+                }
+            }),
+            TimerJob::Periodic { mut f, period, mode } =>
+            {
+                let shared = self.clone();
+                let deadline = entry.deadline;
+                Box::new(move ||
+                {
+                    if cancelled.load(Ordering::Acquire)
+                    {
+                        return;
+                    }
+
+                    //  パニックになっても次の実行を登録してからパニックを再開
+                    let result = catch_unwind(AssertUnwindSafe(&mut f));
+                    let next = match mode
+                    {
+                        Periodic::FixedRate => deadline_after(deadline, period),
+                        Periodic::FixedDelay => deadline_after(Instant::now(), period),
+                    };
+                    shared.insert(next, cancelled, TimerJob::Periodic { f, period, mode });
+                    if let Err(payload) = result
+                    {
+                        resume_unwind(payload);
+                    }
+                })
+            },
+        };
+
+        match inner.queue.push(job, Priority::Normal)
+        {
+            Ok(()) =>
+            {
+                let _ignored = inner.start_threads().and_then(|()| inner.grow_if_backlogged());
+            },
+            //  キューが一杯の場合は少し待ってから再試行
+            Err(PushError::Full(job)) =>
+            {
+                self.insert(Instant::now() + RETRY_DELAY, entry.cancelled, TimerJob::Once(job));
+            },
+            Err(PushError::Closed(_)) => {},
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  Timer
+//------------------------------------------------------------------------------
+pub(crate) struct Timer
+{
+    shared: Arc<TimerShared>,
+}
+
+impl Timer
+{
+    //--------------------------------------------------------------------------
+    //  新しいタイマーを生成
+    //--------------------------------------------------------------------------
+    pub(crate) fn new() -> Self
+    {
+        Self
+        {
+            shared: Arc::new(TimerShared
+            {
+                state: Mutex::new(TimerState
+                {
+                    entries: BinaryHeap::new(),
+                    next_seq: 0,
+                    started: false,
+                    closed: false,
+                }),
+                condvar: Condvar::new(),
+            }),
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  ジョブを登録
+    //  必要であればタイマースレッドを起動する
+    //--------------------------------------------------------------------------
+    fn schedule( &self, inner: &Arc<Inner>, deadline: Instant, job: TimerJob ) -> TimerHandle
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if self.shared.insert(deadline, cancelled.clone(), job)
+        {
+            let shared = self.shared.clone();
+            let weak = Arc::downgrade(inner);
+            let spawned = std::thread::Builder::new()
+                .name(format!("{}-timer", inner.name))
+                .spawn(move || shared.run(weak));
+
+            //  起動に失敗した場合は次の登録時に再試行する
+            if spawned.is_err()
+            {
+                self.shared.state.lock().unwrap().started = false;
+            }
+        }
+        TimerHandle { cancelled }
+    }
+
+    //--------------------------------------------------------------------------
+    //  タイマーを閉じて、タイマースレッドを停止
+    //--------------------------------------------------------------------------
+    pub(crate) fn close( &self )
+    {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.condvar.notify_all();
+
+        //  タイマースレッドが起動していなければここで破棄
+        if !state.started
+        {
+            let entries = std::mem::take(&mut state.entries);
+            drop(state);
+            drop(entries);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//  TimerHandle
+//------------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct TimerHandle
+{
+    //  キャンセルされたかどうか
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle
+{
+    //--------------------------------------------------------------------------
+    //  ジョブをキャンセル
+    //--------------------------------------------------------------------------
+    pub fn cancel( &self )
+    {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    //--------------------------------------------------------------------------
+    //  キャンセルされたかどうか
+    //--------------------------------------------------------------------------
+    pub fn is_cancelled( &self ) -> bool
+    {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+impl Debug for TimerHandle
+{
+    //--------------------------------------------------------------------------
+    //  fmt
+    //--------------------------------------------------------------------------
+    fn fmt( &self, f: &mut Formatter<'_> ) -> Result<(), core::fmt::Error>
+    {
+        write!(f, "TimerHandle{{cancelled={:?}}}", self.is_cancelled())
+    }
+}
+
+impl ThreadPool
+{
+    //--------------------------------------------------------------------------
+    //  指定した時間の経過後にジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_after<F>( &self, delay: Duration, f: F ) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.schedule_at(deadline_after(Instant::now(), delay), f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  指定した時刻にジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_at<F>( &self, deadline: Instant, f: F ) -> TimerHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.inner.timer.schedule(&self.inner, deadline, TimerJob::Once(Box::new(f)))
+    }
+
+    //--------------------------------------------------------------------------
+    //  一定の周期でジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_every<F>( &self, period: Duration, f: F ) -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.schedule_every_with_mode(Periodic::FixedRate, period, f)
+    }
+
+    //--------------------------------------------------------------------------
+    //  実行間隔の扱いを指定して、一定の周期でジョブをスケジュール
+    //--------------------------------------------------------------------------
+    pub fn schedule_every_with_mode<F>( &self, mode: Periodic, period: Duration, f: F )
+        -> TimerHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        assert!(!period.is_zero(), "period must be greater than zero");
+
+        let job = TimerJob::Periodic { f: Box::new(f), period, mode };
+        self.inner.timer.schedule(&self.inner, deadline_after(Instant::now(), period), job)
+    }
+}
+
+//------------------------------------------------------------------------------
+//  テスト
+//------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests
+{
+    use super::Periodic;
+    use crate::threadpool::ThreadPool;
+    use core::time::Duration;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::{ Arc, Mutex };
+    use std::time::Instant;
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_after
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_after()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let start = Instant::now();
+        let sender_clone = sender.clone();
+        pool.schedule_after(Duration::from_millis(50), move || sender_clone.send(2).unwrap());
+        pool.schedule_at(start + Duration::from_millis(10), move || sender.send(1).unwrap());
+
+        assert_eq!(1, receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert_eq!(2, receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_timer_cancel
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_timer_cancel()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        let handle = pool.schedule_after(Duration::from_millis(20), move ||
+        {
+            counter_clone.fetch_add(1, Ordering::AcqRel);
+        });
+        handle.cancel();
+        assert!(handle.is_cancelled());
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule_after(Duration::from_millis(50), move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(0, counter.load(Ordering::Acquire));
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_far_future
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_far_future()
+    {
+        //  `Instant` で表せない期限でもpanicせずに登録できる
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let after = pool.schedule_after(Duration::MAX, || unreachable!());
+        let every = pool.schedule_every_with_mode
+        (
+            Periodic::FixedDelay,
+            Duration::MAX,
+            || unreachable!()
+        );
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.schedule_after(Duration::from_millis(10), move || sender.send(()).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        after.cancel();
+        every.cancel();
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_every
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_every()
+    {
+        let pool = ThreadPool::new("test", 2).unwrap();
+        for mode in [Periodic::FixedRate, Periodic::FixedDelay]
+        {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let sender = Mutex::new(sender);
+            let mut count = 0;
+            let handle = pool.schedule_every_with_mode(mode, Duration::from_millis(5), move ||
+            {
+                count += 1;
+                let _ignored = sender.lock().unwrap().send(count);
+            });
+            for n in 1..=3
+            {
+                assert_eq!(n, receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+            }
+            handle.cancel();
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    //  test_schedule_every_panic
+    //--------------------------------------------------------------------------
+    #[test]
+    fn test_schedule_every_panic()
+    {
+        let pool = ThreadPool::new("test", 1).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let counter_clone = counter.clone();
+        let handle = pool.schedule_every(Duration::from_millis(5), move ||
+        {
+            counter_clone.fetch_add(1, Ordering::AcqRel);
+            panic!("periodic job panicked");
+        });
+
+        //  パニックになっても周期ジョブは続く
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.num_panicked_jobs() < 3
+        {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        handle.cancel();
+        assert!(counter.load(Ordering::Acquire) >= 3);
+    }
+}